@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use image_worker::image::{Colors, PixelSize, Sampling, Transform, TransformMode};
+
+fn source() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(2000, 1500, |x, y| Rgba {
+        data: [(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, 255],
+    }))
+}
+
+fn transform(mode: TransformMode, scale: f32) -> Transform {
+    let mut transform = Transform::new(
+        &PixelSize {
+            width: 2000,
+            height: 1500,
+        },
+        mode,
+    );
+    transform.scale = scale;
+    transform
+}
+
+fn process(c: &mut Criterion) {
+    let cases = [
+        (
+            "limit",
+            transform(
+                TransformMode::Limit {
+                    width: 400,
+                    height: 300,
+                },
+                1.0,
+            ),
+        ),
+        (
+            "fill, scaled 4x",
+            transform(
+                TransformMode::Fill {
+                    width: 400,
+                    height: 300,
+                },
+                4.0,
+            ),
+        ),
+        (
+            "fit width, scaled 10x",
+            transform(TransformMode::FitWidth(400), 10.0),
+        ),
+    ];
+
+    for (name, transform) in cases.iter() {
+        c.bench_function(name, |b| {
+            let mut image = source();
+            b.iter(|| {
+                image_worker::image::process(
+                    &mut image,
+                    transform,
+                    &Sampling::default(),
+                    ImageOutputFormat::PNG,
+                    &Colors::default(),
+                )
+                .unwrap()
+            })
+        });
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = process
+}
+criterion_main!(benches);
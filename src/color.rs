@@ -0,0 +1,358 @@
+/// Parses a CSS color into straight RGBA: hex with or without `#` in the
+/// `rgb`, `rgba`, `rrggbb` and `rrggbbaa` forms, `rgb()`, `rgba()`, `hsl()`,
+/// `hsla()` and named colors, including `transparent`.
+pub fn parse(color_string: &str) -> Result<[u8; 4], failure::Error> {
+    let color_string = color_string.trim().to_ascii_lowercase();
+    let invalid = || failure::format_err!("invalid color {}", color_string);
+
+    if let Some(arguments) = function_arguments(&color_string, &["rgb", "rgba"]) {
+        let (channels, alpha) = split_arguments(arguments).ok_or_else(invalid)?;
+        let mut rgb = [0; 3];
+
+        for (channel, value) in rgb.iter_mut().zip(channels.iter()) {
+            *channel = match value.strip_suffix('%') {
+                Some(percent) => to_u8(parse_number(percent).ok_or_else(invalid)? / 100.0),
+                None => to_u8(parse_number(value).ok_or_else(invalid)? / 255.0),
+            };
+        }
+
+        return Ok([
+            rgb[0],
+            rgb[1],
+            rgb[2],
+            parse_alpha(alpha).ok_or_else(invalid)?,
+        ]);
+    }
+
+    if let Some(arguments) = function_arguments(&color_string, &["hsl", "hsla"]) {
+        let (channels, alpha) = split_arguments(arguments).ok_or_else(invalid)?;
+
+        let hue = parse_number(channels[0].trim_end_matches("deg")).ok_or_else(invalid)?;
+        let saturation = parse_percentage(channels[1]).ok_or_else(invalid)?;
+        let lightness = parse_percentage(channels[2]).ok_or_else(invalid)?;
+        let rgb = hsl_to_rgb(hue, saturation, lightness);
+
+        return Ok([
+            to_u8(rgb[0]),
+            to_u8(rgb[1]),
+            to_u8(rgb[2]),
+            parse_alpha(alpha).ok_or_else(invalid)?,
+        ]);
+    }
+
+    if let Some(color) = named(&color_string) {
+        return Ok(color);
+    }
+
+    parse_hex(color_string.trim_start_matches('#')).ok_or_else(invalid)
+}
+
+fn parse_hex(hex: &str) -> Option<[u8; 4]> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digit = |i: usize| u8::from_str_radix(&hex[i..=i], 16).ok();
+    let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+    match hex.len() {
+        3 | 4 => {
+            let mut color = [255; 4];
+            for (i, channel) in color.iter_mut().enumerate().take(hex.len()) {
+                *channel = digit(i)? * 17;
+            }
+            Some(color)
+        }
+        6 | 8 => {
+            let mut color = [255; 4];
+            for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+                *channel = pair(i * 2)?;
+            }
+            Some(color)
+        }
+        _ => None,
+    }
+}
+
+fn function_arguments<'a>(color_string: &'a str, names: &[&str]) -> Option<&'a str> {
+    let open = color_string.find('(')?;
+
+    if !names.contains(&color_string[..open].trim_end()) {
+        return None;
+    }
+
+    color_string[open + 1..].strip_suffix(')')
+}
+
+/// Splits `r, g, b, a` or `r g b / a` into three channels and the alpha.
+fn split_arguments(arguments: &str) -> Option<([&str; 3], Option<&str>)> {
+    let (channels, alpha) = match arguments.find('/') {
+        Some(slash) => (&arguments[..slash], Some(arguments[slash + 1..].trim())),
+        None => (arguments, None),
+    };
+
+    let values: Vec<&str> = channels
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    match (values.len(), alpha) {
+        (3, _) => Some(([values[0], values[1], values[2]], alpha)),
+        (4, None) => Some(([values[0], values[1], values[2]], Some(values[3]))),
+        _ => None,
+    }
+}
+
+fn parse_alpha(alpha: Option<&str>) -> Option<u8> {
+    match alpha {
+        None => Some(255),
+        Some(alpha) => match alpha.strip_suffix('%') {
+            Some(percent) => Some(to_u8(parse_number(percent)? / 100.0)),
+            None => Some(to_u8(parse_number(alpha)?)),
+        },
+    }
+}
+
+fn parse_percentage(value: &str) -> Option<f32> {
+    Some(parse_number(value.strip_suffix('%')?)? / 100.0)
+}
+
+fn parse_number(value: &str) -> Option<f32> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Converts `hue` in degrees, `saturation` and `lightness` from `0.0..=1.0`.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let saturation = saturation.clamp(0.0, 1.0);
+    let lightness = lightness.clamp(0.0, 1.0);
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+
+    [r + m, g + m, b + m]
+}
+
+fn named(name: &str) -> Option<[u8; 4]> {
+    if name == "transparent" {
+        return Some([0; 4]);
+    }
+
+    NAMED_COLORS
+        .binary_search_by_key(&name, |(color_name, _)| color_name)
+        .ok()
+        .map(|i| {
+            let rgb = NAMED_COLORS[i].1;
+            [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255]
+        })
+}
+
+/// The CSS named colors, sorted by name.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+#[cfg(test)]
+mod test {
+    use super::{parse, NAMED_COLORS};
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse("abc").unwrap(), [0xAA, 0xBB, 0xCC, 255]);
+        assert_eq!(parse("#abc8").unwrap(), [0xAA, 0xBB, 0xCC, 0x88]);
+        assert_eq!(parse("#0080FF").unwrap(), [0, 0x80, 0xFF, 255]);
+        assert_eq!(parse("0080ff40").unwrap(), [0, 0x80, 0xFF, 0x40]);
+        assert!(parse("#12345").is_err());
+        assert!(parse("#ggg").is_err());
+    }
+
+    #[test]
+    fn parses_color_functions() {
+        assert_eq!(parse("rgb(255, 0, 128)").unwrap(), [255, 0, 128, 255]);
+        assert_eq!(parse("rgba(255,0,128,0.5)").unwrap(), [255, 0, 128, 128]);
+        assert_eq!(parse("rgb(100% 0% 50% / 25%)").unwrap(), [255, 0, 128, 64]);
+        assert_eq!(parse("hsl(120, 100%, 50%)").unwrap(), [0, 255, 0, 255]);
+        assert_eq!(
+            parse("hsla(240deg 100% 25% / 0.5)").unwrap(),
+            [0, 0, 128, 128]
+        );
+        assert_eq!(parse("hsl(0, 0%, 100%)").unwrap(), [255, 255, 255, 255]);
+        assert!(parse("rgb(1, 2)").is_err());
+        assert!(parse("hsl(1, 2, 3)").is_err());
+        assert!(parse("cmyk(0, 0, 0, 0)").is_err());
+    }
+
+    #[test]
+    fn parses_named_colors() {
+        assert_eq!(parse("RebeccaPurple").unwrap(), [0x66, 0x33, 0x99, 255]);
+        assert_eq!(parse("transparent").unwrap(), [0, 0, 0, 0]);
+        assert!(parse("reddish").is_err());
+
+        for pair in NAMED_COLORS.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{} is out of order", pair[1].0);
+        }
+    }
+}
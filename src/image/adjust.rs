@@ -0,0 +1,372 @@
+use super::parallel;
+use super::resample::{linear_to_srgb, srgb_to_linear, FloatImage};
+
+/// Tonal adjustments to the resampled image, made in the order of the fields
+/// on sRGB values, each clamped to `0.0..=1.0`. The defaults change nothing.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Adjustments {
+    /// Stops to multiply the linear light by, as a power of 2.
+    pub exposure: f32,
+    /// Raises values to the power of `1 / gamma`, so that more than 1 lightens.
+    pub gamma: f32,
+    /// Multiplies values, like the CSS `brightness()` filter.
+    pub brightness: f32,
+    /// Scales the distance of values from the middle grey, like `contrast()`.
+    pub contrast: f32,
+    /// 0 for grey and more than 1 for more vivid colors, like `saturate()`.
+    pub saturation: f32,
+    /// Degrees to rotate the hue by, like `hue-rotate()`.
+    pub hue: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Adjustments {
+            exposure: 0.0,
+            gamma: 1.0,
+            brightness: 1.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            hue: 0.0,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    /// Adjusts premultiplied pixels, which are in linear light if `linear`.
+    pub fn apply(&self, image: &mut FloatImage, linear: bool) {
+        if self.is_identity() {
+            return;
+        }
+
+        let exposure = 2_f32.powf(self.exposure);
+        let saturation = saturation_matrix(self.saturation);
+        let hue = hue_matrix(self.hue);
+
+        map_srgb(image, linear, |mut rgb| {
+            for value in rgb.iter_mut() {
+                if self.exposure != 0.0 {
+                    *value = linear_to_srgb((srgb_to_linear(*value) * exposure).min(1.0));
+                }
+                *value = value.powf(1.0 / self.gamma).clamp(0.0, 1.0);
+                *value = (*value * self.brightness).clamp(0.0, 1.0);
+                *value = ((*value - 0.5) * self.contrast + 0.5).clamp(0.0, 1.0);
+            }
+
+            multiply(&hue, multiply(&saturation, rgb))
+        });
+    }
+}
+
+/// A preset change to the colors, made after the `Adjustments`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Effect {
+    Grayscale,
+    /// The matrix of the CSS `sepia()` filter.
+    Sepia,
+    /// Maps the luminance from black to white onto `shadow` to `highlight`.
+    Duotone {
+        shadow: [u8; 3],
+        highlight: [u8; 3],
+    },
+}
+
+impl Effect {
+    /// Changes premultiplied pixels, which are in linear light if `linear`.
+    pub fn apply(self, image: &mut FloatImage, linear: bool) {
+        match self {
+            Effect::Grayscale => map_srgb(image, linear, |rgb| {
+                let luminance = luminance(rgb);
+                [luminance; 3]
+            }),
+            Effect::Sepia => map_srgb(image, linear, |rgb| multiply(&SEPIA, rgb)),
+            Effect::Duotone { shadow, highlight } => map_srgb(image, linear, |rgb| {
+                let luminance = luminance(rgb);
+                let mut output = [0.0; 3];
+
+                for (i, value) in output.iter_mut().enumerate() {
+                    let shadow = f32::from(shadow[i]) / 255.0;
+                    let highlight = f32::from(highlight[i]) / 255.0;
+                    *value = shadow + (highlight - shadow) * luminance;
+                }
+
+                output
+            }),
+        }
+    }
+}
+
+/// Maps the unpremultiplied sRGB values of every visible pixel with `f`.
+pub fn map_srgb<F>(image: &mut FloatImage, linear: bool, f: F)
+where
+    F: Fn([f32; 3]) -> [f32; 3] + Sync + Send,
+{
+    map_srgb_at(image, linear, |_, _, rgb| f(rgb));
+}
+
+/// Like `map_srgb`, with the position of each pixel passed to `f`.
+pub fn map_srgb_at<F>(image: &mut FloatImage, linear: bool, f: F)
+where
+    F: Fn(u32, u32, [f32; 3]) -> [f32; 3] + Sync + Send,
+{
+    parallel::for_each_row(&mut image.pixels, image.width as usize, |y, row| {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let alpha = pixel[3].clamp(0.0, 1.0);
+            if alpha <= 0.0 {
+                // Ringing may leave colors that a background would show
+                pixel[..3].copy_from_slice(&[0.0; 3]);
+                continue;
+            }
+
+            let mut rgb = [0.0; 3];
+            for (value, channel) in rgb.iter_mut().zip(pixel.iter()) {
+                *value = (channel / alpha).clamp(0.0, 1.0);
+                if linear {
+                    *value = linear_to_srgb(*value);
+                }
+            }
+
+            for (channel, value) in pixel.iter_mut().zip(f(x as u32, y as u32, rgb).iter()) {
+                let value = value.clamp(0.0, 1.0);
+                *channel = if linear { srgb_to_linear(value) } else { value } * alpha;
+            }
+        }
+    });
+}
+
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    (0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]).clamp(0.0, 1.0)
+}
+
+type Matrix = [[f32; 3]; 3];
+
+fn multiply(matrix: &Matrix, rgb: [f32; 3]) -> [f32; 3] {
+    let mut output = [0.0; 3];
+
+    for (value, row) in output.iter_mut().zip(matrix.iter()) {
+        *value = (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]).clamp(0.0, 1.0);
+    }
+
+    output
+}
+
+const SEPIA: Matrix = [
+    [0.393, 0.769, 0.189],
+    [0.349, 0.686, 0.168],
+    [0.272, 0.534, 0.131],
+];
+
+/// The matrix of the CSS `saturate()` filter.
+fn saturation_matrix(s: f32) -> Matrix {
+    [
+        [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+        [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+        [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+    ]
+}
+
+/// The matrix of the CSS `hue-rotate()` filter.
+fn hue_matrix(degrees: f32) -> Matrix {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+
+    [
+        [
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+        ],
+        [
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+        ],
+        [
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+        ],
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Adjustments, Effect};
+    use crate::image::resample::FloatImage;
+
+    fn adjust(adjustments: Adjustments, rgba: [u8; 4]) -> [u8; 4] {
+        let mut image =
+            FloatImage::from_rgba(&image::RgbaImage::from_raw(1, 1, rgba.to_vec()).unwrap());
+        image.premultiply();
+        adjustments.apply(&mut image, false);
+        image.unpremultiply();
+
+        image.to_rgba().get_pixel(0, 0).data
+    }
+
+    #[test]
+    fn adjusts_to_reference_values() {
+        let grey = [128, 128, 128, 255];
+        let red = [255, 0, 0, 255];
+
+        let cases = [
+            (
+                Adjustments {
+                    exposure: 1.0,
+                    ..Default::default()
+                },
+                grey,
+                [176, 176, 176, 255],
+            ),
+            (
+                Adjustments {
+                    exposure: -1.0,
+                    ..Default::default()
+                },
+                grey,
+                [92, 92, 92, 255],
+            ),
+            (
+                Adjustments {
+                    gamma: 2.2,
+                    ..Default::default()
+                },
+                grey,
+                [186, 186, 186, 255],
+            ),
+            (
+                Adjustments {
+                    brightness: 0.5,
+                    ..Default::default()
+                },
+                [200, 100, 0, 255],
+                [100, 50, 0, 255],
+            ),
+            (
+                Adjustments {
+                    brightness: 2.0,
+                    ..Default::default()
+                },
+                [200, 100, 0, 255],
+                [255, 200, 0, 255],
+            ),
+            (
+                Adjustments {
+                    contrast: 1.5,
+                    ..Default::default()
+                },
+                [64, 128, 192, 255],
+                [32, 128, 224, 255],
+            ),
+            (
+                Adjustments {
+                    contrast: 0.0,
+                    ..Default::default()
+                },
+                [64, 128, 192, 255],
+                [128, 128, 128, 255],
+            ),
+            (
+                Adjustments {
+                    saturation: 0.0,
+                    ..Default::default()
+                },
+                red,
+                [54, 54, 54, 255],
+            ),
+            (
+                Adjustments {
+                    hue: 180.0,
+                    ..Default::default()
+                },
+                red,
+                [0, 109, 109, 255],
+            ),
+            (
+                Adjustments {
+                    hue: 360.0,
+                    ..Default::default()
+                },
+                red,
+                red,
+            ),
+        ];
+
+        for (adjustments, input, expected) in cases.iter() {
+            assert_eq!(adjust(*adjustments, *input), *expected, "{:?}", adjustments);
+        }
+    }
+
+    #[test]
+    fn applies_effects() {
+        let orange = [255, 128, 0, 255];
+        let cases = [
+            (Effect::Grayscale, orange, [146, 146, 146, 255]),
+            (Effect::Sepia, orange, [199, 177, 138, 255]),
+            (
+                Effect::Duotone {
+                    shadow: [0, 0, 128],
+                    highlight: [255, 255, 0],
+                },
+                orange,
+                [146, 146, 55, 255],
+            ),
+            (
+                Effect::Duotone {
+                    shadow: [0, 0, 128],
+                    highlight: [255, 255, 0],
+                },
+                [0, 0, 0, 128],
+                [0, 0, 128, 128],
+            ),
+        ];
+
+        for (effect, input, expected) in cases.iter() {
+            let pixel = image::RgbaImage::from_raw(1, 1, input.to_vec()).unwrap();
+            let mut image = FloatImage::from_rgba(&pixel);
+            image.premultiply();
+            effect.apply(&mut image, false);
+            image.unpremultiply();
+
+            assert_eq!(
+                image.to_rgba().get_pixel(0, 0).data,
+                *expected,
+                "{:?}",
+                effect
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_alpha_and_transparent_pixels() {
+        let adjustments = Adjustments {
+            brightness: 0.5,
+            ..Default::default()
+        };
+
+        assert_eq!(adjust(adjustments, [200, 100, 0, 128]), [100, 50, 0, 128]);
+        assert_eq!(adjust(adjustments, [0, 0, 0, 0]), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn adjusts_linear_pixels_like_srgb_ones() {
+        let adjustments = Adjustments {
+            gamma: 2.2,
+            saturation: 0.5,
+            ..Default::default()
+        };
+        let pixel = image::RgbaImage::from_raw(1, 1, vec![200, 100, 50, 255]).unwrap();
+
+        let mut srgb = FloatImage::from_rgba(&pixel);
+        adjustments.apply(&mut srgb, false);
+
+        let mut linear = FloatImage::from_rgba_linear(&pixel);
+        adjustments.apply(&mut linear, true);
+        linear.linear_to_srgb();
+
+        assert_eq!(srgb.to_rgba().into_raw(), linear.to_rgba().into_raw());
+    }
+}
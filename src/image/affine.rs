@@ -0,0 +1,227 @@
+/// A 2D affine mapping, laid out like a CSS `matrix(a, b, c, d, e, f)`:
+///
+/// ```text
+/// x' = a * x + c * y + e
+/// y' = b * x + d * y + f
+/// ```
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Affine {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl Affine {
+    pub fn identity() -> Self {
+        Affine {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 1.0,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    pub fn translate(dx: f32, dy: f32) -> Self {
+        Affine {
+            e: dx,
+            f: dy,
+            ..Affine::identity()
+        }
+    }
+
+    pub fn scale(sx: f32, sy: f32) -> Self {
+        Affine {
+            a: sx,
+            d: sy,
+            ..Affine::identity()
+        }
+    }
+
+    /// Rotates clockwise (with y pointing down) by whole quarter turns, which
+    /// keeps the matrix exact instead of going through `sin` and `cos`.
+    pub fn quarter_turns(turns: u32) -> Self {
+        let (cos, sin) = match turns % 4 {
+            0 => (1.0, 0.0),
+            1 => (0.0, 1.0),
+            2 => (-1.0, 0.0),
+            _ => (0.0, -1.0),
+        };
+
+        Affine {
+            a: cos,
+            b: sin,
+            c: -sin,
+            d: cos,
+            ..Affine::identity()
+        }
+    }
+
+    /// Moves the rectangle at `x`, `y` to the origin, so that a canvas of its
+    /// size keeps what falls inside of it and cuts away the rest.
+    pub fn crop(x: f32, y: f32, width: f32, height: f32) -> Crop {
+        Crop {
+            affine: Affine::translate(-x, -y),
+            width,
+            height,
+        }
+    }
+
+    /// Returns the mapping that applies `self` first and `next` after it.
+    pub fn then(&self, next: &Affine) -> Self {
+        Affine {
+            a: next.a * self.a + next.c * self.b,
+            b: next.b * self.a + next.d * self.b,
+            c: next.a * self.c + next.c * self.d,
+            d: next.b * self.c + next.d * self.d,
+            e: next.a * self.e + next.c * self.f + next.e,
+            f: next.b * self.e + next.d * self.f + next.f,
+        }
+    }
+
+    pub fn invert(&self) -> Option<Self> {
+        let determinant = self.a * self.d - self.b * self.c;
+
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+
+        Some(Affine {
+            a: self.d / determinant,
+            b: -self.b / determinant,
+            c: -self.c / determinant,
+            d: self.a / determinant,
+            e: (self.c * self.f - self.d * self.e) / determinant,
+            f: (self.b * self.e - self.a * self.f) / determinant,
+        })
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.e,
+            self.b * x + self.d * y + self.f,
+        )
+    }
+
+    /// The bounding box of the rectangle at `x`, `y` once mapped, as its
+    /// top-left corner and size.
+    pub fn map_rect(&self, x: f32, y: f32, width: f32, height: f32) -> (f32, f32, f32, f32) {
+        let corners = [
+            self.apply(x, y),
+            self.apply(x + width, y),
+            self.apply(x, y + height),
+            self.apply(x + width, y + height),
+        ];
+
+        let (mut left, mut top) = corners[0];
+        let (mut right, mut bottom) = corners[0];
+        for &(x, y) in corners[1..].iter() {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+
+        (left, top, right - left, bottom - top)
+    }
+
+    /// Whether source rows stay rows and columns stay columns, in which case
+    /// resampling can be done one axis at a time.
+    pub fn is_axis_aligned(&self) -> bool {
+        self.b == 0.0 && self.c == 0.0
+    }
+}
+
+/// A mapping together with the size of the canvas that bounds it.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Crop {
+    pub affine: Affine,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Crop {
+    /// Returns the crop that applies `previous` before this one.
+    pub fn after(&self, previous: &Affine) -> Self {
+        Crop {
+            affine: previous.then(&self.affine),
+            ..*self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Affine;
+
+    #[test]
+    fn composes_in_order() {
+        let affine = Affine::translate(10.0, 0.0).then(&Affine::scale(2.0, 3.0));
+
+        assert_eq!(affine.apply(1.0, 1.0), (22.0, 3.0));
+
+        let affine = Affine::scale(2.0, 3.0).then(&Affine::translate(10.0, 0.0));
+
+        assert_eq!(affine.apply(1.0, 1.0), (12.0, 3.0));
+    }
+
+    #[test]
+    fn rotates_clockwise_by_quarter_turns() {
+        assert_eq!(Affine::quarter_turns(0), Affine::identity());
+        assert_eq!(Affine::quarter_turns(1).apply(1.0, 0.0), (0.0, 1.0));
+        assert_eq!(Affine::quarter_turns(2).apply(1.0, 0.0), (-1.0, 0.0));
+        assert_eq!(Affine::quarter_turns(3).apply(1.0, 0.0), (0.0, -1.0));
+        assert_eq!(Affine::quarter_turns(5), Affine::quarter_turns(1));
+        assert!(!Affine::quarter_turns(1).is_axis_aligned());
+        assert!(Affine::quarter_turns(2).is_axis_aligned());
+    }
+
+    #[test]
+    fn maps_rectangles_to_bounding_boxes() {
+        let affine = Affine::quarter_turns(1).then(&Affine::scale(2.0, 0.5));
+
+        assert_eq!(
+            affine.map_rect(0.0, 0.0, 100.0, 50.0),
+            (-100.0, 0.0, 100.0, 50.0)
+        );
+        assert_eq!(
+            Affine::translate(3.0, -4.0).map_rect(1.0, 2.0, 10.0, 20.0),
+            (4.0, -2.0, 10.0, 20.0)
+        );
+    }
+
+    #[test]
+    fn inverts_mappings() {
+        let affine = Affine::scale(-1.0, 1.0)
+            .then(&Affine::quarter_turns(1))
+            .then(&Affine::scale(0.5, 0.25))
+            .then(&Affine::translate(-3.5, 12.0));
+        let inverse = affine.invert().unwrap();
+
+        let (x, y) = affine.apply(7.0, -2.0);
+        let (x, y) = inverse.apply(x, y);
+
+        assert!((x - 7.0).abs() < 1e-5);
+        assert!((y + 2.0).abs() < 1e-5);
+        assert_eq!(Affine::scale(0.0, 1.0).invert(), None);
+    }
+
+    #[test]
+    fn crops_after_rotating_and_flipping() {
+        let flip = Affine::scale(-1.0, 1.0);
+        let rotate = Affine::quarter_turns(1);
+        let crop = Affine::crop(-40.0, -90.0, 20.0, 30.0).after(&flip.then(&rotate));
+
+        assert_eq!((crop.width, crop.height), (20.0, 30.0));
+        assert_eq!(crop.affine.apply(80.0, 30.0), (10.0, 10.0));
+        assert_eq!(crop.affine.apply(70.0, 20.0), (20.0, 20.0));
+        assert_eq!(
+            crop.affine.map_rect(0.0, 0.0, 100.0, 50.0),
+            (-10.0, -10.0, 50.0, 100.0)
+        );
+    }
+}
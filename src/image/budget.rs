@@ -0,0 +1,293 @@
+use super::resample::Filter;
+use super::retarget::Plan;
+use super::transform::{PixelSize, Transform};
+use super::Sampling;
+
+use image::{ImageFormat, ImageOutputFormat};
+
+/// Rough costs per pixel, in the same units as the budget.
+const DECODE_COST: f32 = 8.0;
+const CONVERT_COST: f32 = 2.0;
+const ENCODE_COST: f32 = 20.0;
+const FAST_ENCODE_COST: f32 = 5.0;
+/// Per pixel visited while finding seams.
+const RETARGET_COST: f32 = 12.0;
+
+/// Ways in which a request was made cheaper to stay within its budget.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Degradations {
+    /// Resampled with `Filter::Triangle` instead of a wider filter.
+    pub filter: bool,
+    /// PNGs are compressed faster, making them larger.
+    pub encode: bool,
+    /// JPEGs are decoded at half the resolution that the output needs.
+    pub decode: bool,
+}
+
+impl Degradations {
+    /// The names of the degradations that were taken, in the order of the
+    /// fields.
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            ("filter", self.filter),
+            ("encode", self.encode),
+            ("decode", self.decode),
+        ]
+        .iter()
+        .filter(|&&(_, taken)| taken)
+        .map(|&(name, _)| name)
+        .collect()
+    }
+
+    pub fn decode_size(self, transform: &Transform) -> PixelSize {
+        let size = transform.get_decode_size();
+
+        if self.decode {
+            PixelSize {
+                width: (size.width / 2).max(1),
+                height: (size.height / 2).max(1),
+            }
+        } else {
+            size
+        }
+    }
+}
+
+/// Estimates the cost of a request from its input and output pixel counts, in
+/// roughly the number of operations on a single channel.
+pub fn estimate(
+    input_size: &PixelSize,
+    input_format: ImageFormat,
+    transform: &Transform,
+    sampling: &Sampling,
+    output_format: &ImageOutputFormat,
+    degradations: Degradations,
+) -> u64 {
+    let decoded = decoded_size(input_size, input_format, transform, degradations);
+    let decoded_pixels = pixels(&decoded);
+
+    let canvas = transform.get_output_pixel_dimensions().canvas;
+    let canvas_pixels = pixels(&canvas);
+
+    // Source pixels per canvas pixel along each axis, which box reduction
+    // brings down to at most 4, and the filter taps that covers
+    let affine = transform.get_affine_for(&decoded);
+    let footprint_x = (1.0 / affine.a.hypot(affine.b)).min(decoded.width as f32);
+    let footprint_y = (1.0 / affine.c.hypot(affine.d)).min(decoded.height as f32);
+    let support = match sampling.filter {
+        Some(filter) => filter.support(),
+        None => Filter::for_affine(&affine).support(),
+    };
+    let reduced_x = footprint_x.clamp(1.0, 4.0);
+    let reduced_y = footprint_y.clamp(1.0, 4.0);
+
+    let resample = canvas_pixels * 2.0 * support * (reduced_x * reduced_y + reduced_y);
+
+    let retarget = match transform.get_retarget_size() {
+        Some(size) => Plan::new(&decoded, &size).seam_pixels() as f32 * RETARGET_COST,
+        None => 0.0,
+    };
+
+    let encode = match output_format {
+        ImageOutputFormat::PNG if degradations.encode => FAST_ENCODE_COST,
+        _ => ENCODE_COST,
+    };
+
+    (decoded_pixels * (DECODE_COST + CONVERT_COST) + resample + retarget + canvas_pixels * encode)
+        as u64
+}
+
+/// Degrades `sampling` and picks further degradations, cheapest to notice
+/// first, until the estimated cost fits within `budget`. A budget of 0 is
+/// unlimited. The request may still exceed the budget after all of them.
+pub fn degrade(
+    budget: u64,
+    input_size: &PixelSize,
+    input_format: ImageFormat,
+    transform: &Transform,
+    sampling: &mut Sampling,
+    output_format: &ImageOutputFormat,
+) -> Degradations {
+    let mut degradations = Degradations::default();
+    let fits = |sampling: &Sampling, degradations: Degradations| {
+        budget == 0
+            || estimate(
+                input_size,
+                input_format,
+                transform,
+                sampling,
+                output_format,
+                degradations,
+            ) <= budget
+    };
+
+    if fits(sampling, degradations) {
+        return degradations;
+    }
+
+    // The filter that processing picks for the decoded source
+    let decoded = decoded_size(input_size, input_format, transform, degradations);
+    let filter = sampling
+        .filter
+        .unwrap_or_else(|| Filter::for_affine(&transform.get_affine_for(&decoded)));
+    if filter.support() > Filter::Triangle.support() {
+        sampling.filter = Some(Filter::Triangle);
+        degradations.filter = true;
+
+        if fits(sampling, degradations) {
+            return degradations;
+        }
+    }
+
+    if let ImageOutputFormat::PNG = output_format {
+        degradations.encode = true;
+
+        if fits(sampling, degradations) {
+            return degradations;
+        }
+    }
+
+    // Unless the output is so small that halving the decode size changes nothing
+    let halved = Degradations {
+        decode: true,
+        ..degradations
+    };
+    if input_format == ImageFormat::JPEG
+        && halved.decode_size(transform) != transform.get_decode_size()
+    {
+        degradations = halved;
+    }
+
+    degradations
+}
+
+/// The size that the source is decoded at, which only JPEGs can reduce.
+fn decoded_size(
+    input_size: &PixelSize,
+    input_format: ImageFormat,
+    transform: &Transform,
+    degradations: Degradations,
+) -> PixelSize {
+    match input_format {
+        ImageFormat::JPEG => degradations.decode_size(transform),
+        _ => PixelSize {
+            width: input_size.width,
+            height: input_size.height,
+        },
+    }
+}
+
+fn pixels(size: &PixelSize) -> f32 {
+    size.width as f32 * size.height as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::{degrade, estimate, Degradations};
+    use crate::image::transform::{PixelSize, Transform, TransformMode};
+    use crate::image::{Filter, Sampling};
+    use image::{ImageFormat, ImageOutputFormat};
+
+    fn thumbnail() -> (PixelSize, Transform) {
+        let input_size = PixelSize {
+            width: 6000,
+            height: 4000,
+        };
+        let transform = Transform::new(&input_size, TransformMode::FitWidth(300));
+
+        (input_size, transform)
+    }
+
+    #[test]
+    fn estimates_cheaper_degradations() {
+        let (input_size, transform) = thumbnail();
+        let cost = |degradations| {
+            estimate(
+                &input_size,
+                ImageFormat::JPEG,
+                &transform,
+                &Sampling::default(),
+                &ImageOutputFormat::PNG,
+                degradations,
+            )
+        };
+
+        let full = cost(Degradations::default());
+        let decode = cost(Degradations {
+            decode: true,
+            ..Degradations::default()
+        });
+        let encode = cost(Degradations {
+            encode: true,
+            ..Degradations::default()
+        });
+
+        assert!(decode < full);
+        assert!(encode < full);
+    }
+
+    #[test]
+    fn degrades_until_within_budget() {
+        let (input_size, transform) = thumbnail();
+        let mut sampling = Sampling::default();
+
+        let degradations = degrade(
+            0,
+            &input_size,
+            ImageFormat::JPEG,
+            &transform,
+            &mut sampling,
+            &ImageOutputFormat::PNG,
+        );
+
+        assert_eq!(degradations, Degradations::default());
+        assert_eq!(sampling.filter, None);
+
+        let degradations = degrade(
+            1,
+            &input_size,
+            ImageFormat::JPEG,
+            &transform,
+            &mut sampling,
+            &ImageOutputFormat::PNG,
+        );
+
+        assert_eq!(
+            degradations,
+            Degradations {
+                filter: true,
+                encode: true,
+                decode: true,
+            }
+        );
+        assert_eq!(degradations.names(), vec!["filter", "encode", "decode"]);
+        assert_eq!(sampling.filter, Some(Filter::Triangle));
+    }
+
+    #[test]
+    fn decodes_at_full_scale_when_halving_changes_nothing() {
+        let input_size = PixelSize {
+            width: 6000,
+            height: 4000,
+        };
+        let transform = Transform::new(
+            &input_size,
+            TransformMode::Fit {
+                width: 1,
+                height: 1,
+            },
+        );
+
+        let degradations = degrade(
+            1,
+            &input_size,
+            ImageFormat::JPEG,
+            &transform,
+            &mut Sampling::default(),
+            &ImageOutputFormat::PNG,
+        );
+
+        assert!(degradations.encode);
+        assert!(!degradations.decode);
+    }
+}
@@ -0,0 +1,649 @@
+use super::profile;
+use super::transform::PixelSize;
+
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat};
+use png::HasParameters;
+
+/// Reads the pixel size from the image headers, without decoding pixels
+/// where the format allows it.
+pub fn dimensions(buffer: &[u8], format: ImageFormat) -> Result<PixelSize, failure::Error> {
+    let (width, height) = match format {
+        ImageFormat::JPEG => {
+            let mut decoder = jpeg_decoder::Decoder::new(buffer);
+            decoder.read_info()?;
+            let info = decoder.info().unwrap();
+
+            (u64::from(info.width), u64::from(info.height))
+        }
+        ImageFormat::PNG => image::png::PNGDecoder::new(buffer)?.dimensions(),
+        ImageFormat::GIF => image::gif::Decoder::new(buffer)?.dimensions(),
+        ImageFormat::WEBP => {
+            let header = webp_header(buffer)?;
+
+            (u64::from(header.width), u64::from(header.height))
+        }
+        _ => return Err(failure::format_err!("unsupported input format")),
+    };
+
+    Ok(PixelSize {
+        width: width as u32,
+        height: height as u32,
+    })
+}
+
+/// What the first chunk of a WebP tells, which is `VP8X` for extended files
+/// and otherwise the header of the lossy or lossless frame.
+struct WebpHeader {
+    width: u32,
+    height: u32,
+    /// Whether the `VP8X` flags or the lossless header mark alpha as used.
+    alpha: bool,
+    /// Whether the `VP8X` flags mark the file as animated.
+    animated: bool,
+}
+
+fn webp_header(buffer: &[u8]) -> Result<WebpHeader, failure::Error> {
+    // RIFF header, file size and WEBP, then the chunk header and its data
+    let chunk = buffer
+        .get(12..30)
+        .ok_or_else(|| failure::format_err!("image data ended early"))?;
+    let data = &chunk[8..];
+
+    match &chunk[0..4] {
+        b"VP8 " => {
+            // Frame tag and start code, then 14 bits of size and 2 of scale
+            if data[3..6] != [0x9D, 0x01, 0x2A] {
+                return Err(failure::format_err!("invalid VP8 frame"));
+            }
+
+            Ok(WebpHeader {
+                width: u32::from(u16::from_le_bytes([data[6], data[7]]) & 0x3FFF),
+                height: u32::from(u16::from_le_bytes([data[8], data[9]]) & 0x3FFF),
+                alpha: false,
+                animated: false,
+            })
+        }
+        b"VP8L" => {
+            // Signature, then 14 bits each of width and height less one and
+            // the bit that tells whether alpha is used
+            if data[0] != 0x2F {
+                return Err(failure::format_err!("invalid VP8L frame"));
+            }
+            let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+
+            Ok(WebpHeader {
+                width: (bits & 0x3FFF) + 1,
+                height: (bits >> 14 & 0x3FFF) + 1,
+                alpha: bits >> 28 & 1 != 0,
+                animated: false,
+            })
+        }
+        b"VP8X" => {
+            // Flags and reserved bytes, then 24 bits each of the canvas width
+            // and height less one
+            Ok(WebpHeader {
+                width: u32::from_le_bytes([data[4], data[5], data[6], 0]) + 1,
+                height: u32::from_le_bytes([data[7], data[8], data[9], 0]) + 1,
+                alpha: data[0] & 0x10 != 0,
+                animated: data[0] & 0x02 != 0,
+            })
+        }
+        _ => Err(failure::format_err!("invalid WebP chunk")),
+    }
+}
+
+/// Counts the `ANMF` chunks of animated WebPs, one for each frame.
+fn webp_frames(buffer: &[u8]) -> Result<u64, failure::Error> {
+    if !webp_header(buffer)?.animated {
+        return Ok(1);
+    }
+
+    // RIFF header, file size and WEBP
+    let mut position = 12;
+    let mut frames = 0;
+
+    while let Some(header) = buffer.get(position..position + 8) {
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+
+        if &header[0..4] == b"ANMF" {
+            frames += 1;
+        }
+
+        // Chunks are padded to an even length
+        position = skip_chunk(buffer, position + 8, length)? + length % 2;
+    }
+
+    Ok(frames)
+}
+
+/// Whether `buffer` is a PNG with 16 bits per sample, going by its header.
+pub fn is_png_16(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"\x89PNG\r\n\x1a\n") && buffer.get(24) == Some(&16)
+}
+
+/// Decodes a PNG with 16 bits per sample, rounding the samples to 8 bits
+/// where the decoder of the image crate cuts them off.
+pub fn png_16(buffer: &[u8]) -> Result<DynamicImage, failure::Error> {
+    let mut decoder = png::Decoder::new(buffer);
+    decoder.set(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+
+    let channels = reader.output_color_type().0.samples();
+    let width = info.width as usize;
+    let mut pixels = vec![0; width * info.height as usize * channels];
+    let mut y = 0;
+
+    while let Some((row, adam7)) = reader.next_interlaced_row()? {
+        // The first pixel of the row and the distance to the next one
+        let (left, top, step) = match adam7 {
+            Some((pass, line, _)) => {
+                let (left, top, step_x, step_y) = ADAM7[usize::from(pass) - 1];
+                (left, top + line as usize * step_y, step_x)
+            }
+            None => {
+                y += 1;
+                (0, y - 1, 1)
+            }
+        };
+
+        for (i, pixel) in row.chunks(channels * 2).enumerate() {
+            let start = ((top * width) + left + i * step) * channels;
+            let target = pixels
+                .get_mut(start..start + channels)
+                .ok_or_else(|| failure::format_err!("invalid PNG row"))?;
+
+            for (sample, value) in target.iter_mut().zip(round_16(pixel)) {
+                *sample = value;
+            }
+        }
+    }
+
+    let (width, height) = (info.width, info.height);
+    let image = match channels {
+        1 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        2 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        3 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+    };
+
+    image.ok_or_else(|| failure::format_err!("could not load image"))
+}
+
+/// The first column and row of each Adam7 pass, and its steps along both.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Rounds big endian 16 bit samples to 8 bits.
+pub fn round_16(samples: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    samples.chunks(2).map(|sample| {
+        let value = u32::from(u16::from_be_bytes([sample[0], sample[1]]));
+        ((value * 255 + 32767) / 65535) as u8
+    })
+}
+
+/// Counts the frames of animated images by walking their blocks, without
+/// decoding any of them.
+pub fn frames(buffer: &[u8], format: ImageFormat) -> Result<u64, failure::Error> {
+    match format {
+        ImageFormat::GIF => gif_blocks(buffer).map(|(frames, _)| frames),
+        ImageFormat::PNG => png_frames(buffer),
+        ImageFormat::WEBP => webp_frames(buffer),
+        _ => Ok(1),
+    }
+}
+
+/// Whether the image can have transparent pixels, going by its headers.
+pub fn has_alpha(buffer: &[u8], format: ImageFormat) -> Result<bool, failure::Error> {
+    match format {
+        ImageFormat::GIF => gif_blocks(buffer).map(|(_, transparent)| transparent),
+        ImageFormat::PNG => {
+            let (info, reader) = png::Decoder::new(buffer).read_info()?;
+
+            Ok(info.color_type == png::ColorType::GrayscaleAlpha
+                || info.color_type == png::ColorType::RGBA
+                || reader.info().trns.is_some())
+        }
+        ImageFormat::WEBP => webp_header(buffer).map(|header| header.alpha),
+        _ => Ok(false),
+    }
+}
+
+/// Counts the frames of a GIF, and whether any graphic control extension sets
+/// a transparent color.
+fn gif_blocks(buffer: &[u8]) -> Result<(u64, bool), failure::Error> {
+    let flags = byte(buffer, 10)?;
+    // Header, logical screen descriptor and global color table
+    let mut position = 13 + gif_color_table_size(flags);
+    let mut frames = 0;
+    let mut transparent = false;
+
+    loop {
+        match buffer.get(position) {
+            Some(0x2C) => {
+                frames += 1;
+                // Image descriptor, local color table and LZW code size
+                let flags = byte(buffer, position + 9)?;
+                position =
+                    skip_gif_sub_blocks(buffer, position + 11 + gif_color_table_size(flags))?;
+            }
+            Some(0x21) => {
+                if byte(buffer, position + 1)? == 0xF9 {
+                    transparent |= byte(buffer, position + 3)? & 0x01 != 0;
+                }
+                position = skip_gif_sub_blocks(buffer, position + 2)?;
+            }
+            Some(0x3B) | None => return Ok((frames, transparent)),
+            Some(_) => return Err(failure::format_err!("invalid GIF block")),
+        }
+    }
+}
+
+fn gif_color_table_size(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+fn skip_gif_sub_blocks(buffer: &[u8], mut position: usize) -> Result<usize, failure::Error> {
+    loop {
+        let length = byte(buffer, position)? as usize;
+        position += 1 + length;
+
+        if length == 0 {
+            return Ok(position);
+        }
+    }
+}
+
+/// Reads the frame count of animated PNGs from the `acTL` chunk, which comes
+/// before the image data.
+fn png_frames(buffer: &[u8]) -> Result<u64, failure::Error> {
+    let mut position = 8;
+
+    while let Some(chunk) = buffer.get(position..position + 12) {
+        let length = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+
+        match &chunk[4..8] {
+            b"acTL" => {
+                return Ok(u64::from(u32::from_be_bytes([
+                    chunk[8], chunk[9], chunk[10], chunk[11],
+                ])));
+            }
+            b"IDAT" => break,
+            _ => position = skip_chunk(buffer, position + 12, length)?,
+        }
+    }
+
+    Ok(1)
+}
+
+/// The position after `length` bytes of chunk data at `position`, checked
+/// against the end of the buffer so that forged lengths can not wrap around.
+pub fn skip_chunk(buffer: &[u8], position: usize, length: usize) -> Result<usize, failure::Error> {
+    position
+        .checked_add(length)
+        .filter(|end| *end <= buffer.len())
+        .ok_or_else(|| failure::format_err!("image data ended early"))
+}
+
+fn byte(buffer: &[u8], position: usize) -> Result<u8, failure::Error> {
+    buffer
+        .get(position)
+        .cloned()
+        .ok_or_else(|| failure::format_err!("image data ended early"))
+}
+
+/// Decodes a JPEG using 1/2, 1/4 or 1/8 IDCT scaling, picking the smallest
+/// scale that is still at least `target` in size. CMYK and YCCK images are
+/// converted to RGB, with their embedded ICC profile where it can be used.
+pub fn jpeg_scaled(buffer: &[u8], target: &PixelSize) -> Result<DynamicImage, failure::Error> {
+    let adobe = adobe_transform(buffer);
+
+    let mut decoder = jpeg_decoder::Decoder::new(buffer);
+    decoder.read_info()?;
+
+    // The decoder only accepts four components along with an Adobe segment,
+    // which then marks the inverted CMYK that Adobe applications write
+    let patched;
+    if decoder.info().unwrap().pixel_format == jpeg_decoder::PixelFormat::CMYK32 && adobe.is_none()
+    {
+        patched = with_adobe_segment(buffer);
+        decoder = jpeg_decoder::Decoder::new(&patched[..]);
+    }
+
+    decoder.scale(
+        target.width.min(u32::from(u16::MAX)) as u16,
+        target.height.min(u32::from(u16::MAX)) as u16,
+    )?;
+
+    let mut pixels = decoder.decode()?;
+    let info = decoder.info().unwrap();
+    let width = u32::from(info.width);
+    let height = u32::from(info.height);
+
+    let image = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        jpeg_decoder::PixelFormat::RGB24 => {
+            ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        jpeg_decoder::PixelFormat::CMYK32 => {
+            to_cmyk(&mut pixels, adobe);
+            let icc_profile = decoder.icc_profile();
+
+            ImageBuffer::from_raw(width, height, cmyk_to_rgb(&pixels, icc_profile.as_deref()))
+                .map(DynamicImage::ImageRgb8)
+        }
+    };
+
+    image.ok_or_else(|| failure::format_err!("could not load image"))
+}
+
+/// Reads the color transform of the Adobe APP14 segment: 0 for RGB or CMYK, 1
+/// for YCbCr and 2 for YCCK.
+fn adobe_transform(buffer: &[u8]) -> Option<u8> {
+    let mut position = 2;
+
+    while let Some(header) = buffer.get(position..position + 4) {
+        let length = usize::from(u16::from_be_bytes([header[2], header[3]]));
+
+        match header[1] {
+            // Start of scan, after which the segment can not come
+            0xDA => return None,
+            0xEE => {
+                let data = buffer.get(position + 4..position + 2 + length)?;
+                if data.starts_with(b"Adobe") {
+                    return data.get(11).cloned();
+                }
+            }
+            _ => {}
+        }
+
+        position += 2 + length;
+    }
+
+    None
+}
+
+fn with_adobe_segment(buffer: &[u8]) -> Vec<u8> {
+    let mut patched = Vec::with_capacity(buffer.len() + 16);
+    patched.extend_from_slice(&buffer[..2]);
+    patched.extend_from_slice(b"\xFF\xEE\x00\x0EAdobe\x00\x64\x00\x00\x00\x00\x00");
+    patched.extend_from_slice(&buffer[2..]);
+
+    patched
+}
+
+/// Brings decoded four component pixels to CMYK with 0 for no ink. The decoder
+/// undoes the inversion of Adobe CMYK and YCCK, so only the pixels of images
+/// that were given an Adobe segment by `with_adobe_segment` need inverting.
+fn to_cmyk(pixels: &mut [u8], adobe: Option<u8>) {
+    if adobe.is_none() {
+        for channel in pixels.iter_mut() {
+            *channel = 255 - *channel;
+        }
+    }
+}
+
+fn cmyk_to_rgb(pixels: &[u8], icc_profile: Option<&[u8]>) -> Vec<u8> {
+    if let Some(transform) = icc_profile.and_then(profile::cmyk_to_srgb) {
+        let mut output = vec![0; pixels.len() / 4 * 3];
+        transform.convert(pixels, &mut output);
+
+        return output;
+    }
+
+    let mut output = Vec::with_capacity(pixels.len() / 4 * 3);
+
+    for pixel in pixels.chunks(4) {
+        let k = 255 - u32::from(pixel[3]);
+
+        output.push(((255 - u32::from(pixel[0])) * k / 255) as u8);
+        output.push(((255 - u32::from(pixel[1])) * k / 255) as u8);
+        output.push(((255 - u32::from(pixel[2])) * k / 255) as u8);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dimensions, frames, has_alpha, jpeg_scaled, png_16, profile, PixelSize};
+    use image::Pixel;
+    use image::{GenericImageView, ImageFormat};
+
+    #[test]
+    fn reads_dimensions_from_headers() {
+        let buffer =
+            std::fs::read("./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg").unwrap();
+        let image = image::load_from_memory(&buffer).unwrap();
+
+        assert_eq!(
+            dimensions(&buffer, ImageFormat::JPEG).unwrap(),
+            PixelSize {
+                width: image.width(),
+                height: image.height(),
+            }
+        );
+
+        let buffer = std::fs::read("./tests/input/test_pattern.png").unwrap();
+        let image = image::load_from_memory(&buffer).unwrap();
+
+        assert_eq!(
+            dimensions(&buffer, ImageFormat::PNG).unwrap(),
+            PixelSize {
+                width: image.width(),
+                height: image.height(),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_webp_headers() {
+        // Only the first chunks, which can not be decoded
+        let webp = |chunks: &[u8]| {
+            let mut buffer = b"RIFF".to_vec();
+            buffer.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+            buffer.extend_from_slice(b"WEBP");
+            buffer.extend_from_slice(chunks);
+            buffer
+        };
+        let size = |width, height| PixelSize { width, height };
+
+        let lossy = webp(b"VP8 \x0a\x00\x00\x00\x10\x02\x00\x9d\x01\x2a\x40\x41\x20\x00");
+        assert_eq!(
+            dimensions(&lossy, ImageFormat::WEBP).unwrap(),
+            size(320, 32)
+        );
+
+        let lossless = webp(b"VP8L\x0a\x00\x00\x00\x2f\x3f\xc0\x3f\x00\x00\x00\x00\x00\x00");
+        assert_eq!(
+            dimensions(&lossless, ImageFormat::WEBP).unwrap(),
+            size(64, 256)
+        );
+
+        let extended = webp(b"VP8X\x0a\x00\x00\x00\x00\x00\x00\x00\xff\xff\xff\xff\x3f\x00");
+        assert_eq!(
+            dimensions(&extended, ImageFormat::WEBP).unwrap(),
+            size(1 << 24, 1 << 14)
+        );
+
+        for buffer in [&lossy, &lossless, &extended] {
+            assert_eq!(frames(buffer, ImageFormat::WEBP).unwrap(), 1);
+            assert!(!has_alpha(buffer, ImageFormat::WEBP).unwrap());
+        }
+
+        let lossless = webp(b"VP8L\x0a\x00\x00\x00\x2f\x3f\xc0\x3f\x10\x00\x00\x00\x00\x00");
+        assert!(has_alpha(&lossless, ImageFormat::WEBP).unwrap());
+
+        let extended = webp(b"VP8X\x0a\x00\x00\x00\x10\x00\x00\x00\xff\xff\xff\xff\x3f\x00");
+        assert!(has_alpha(&extended, ImageFormat::WEBP).unwrap());
+
+        let mut animated = b"VP8X\x0a\x00\x00\x00\x02\x00\x00\x00\x0f\x00\x00\x0f\x00\x00".to_vec();
+        animated.extend_from_slice(b"ANIM\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        for _ in 0..3 {
+            animated.extend_from_slice(b"ANMF\x03\x00\x00\x00abc\x00");
+        }
+        let animated = webp(&animated);
+
+        assert_eq!(frames(&animated, ImageFormat::WEBP).unwrap(), 3);
+        assert!(frames(&animated[..animated.len() - 2], ImageFormat::WEBP).is_err());
+
+        assert!(dimensions(&lossy[..25], ImageFormat::WEBP).is_err());
+        assert!(dimensions(&webp(b"JUNK\x0a\x00\x00\x00"), ImageFormat::WEBP).is_err());
+    }
+
+    #[test]
+    fn rounds_interlaced_16_bit_png_samples() {
+        // A 2x1 grey image in two Adam7 passes, one for each pixel
+        let ihdr = [0, 0, 0, 2, 0, 0, 0, 1, 16, 0, 0, 0, 1];
+        let idat = deflate::deflate_bytes_zlib(&[0, 0xC8, 0x00, 0, 0xFF, 0x00]);
+
+        let mut buffer = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (name, data) in [(&b"IHDR"[..], &ihdr[..]), (b"IDAT", &idat), (b"IEND", &[])] {
+            let start = buffer.len() + 4;
+            buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            buffer.extend_from_slice(name);
+            buffer.extend_from_slice(data);
+            let crc = profile::crc32(&buffer[start..]);
+            buffer.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        // Cut off to 8 bits they would be 200 and 255
+        assert_eq!(
+            png_16(&buffer).unwrap().to_luma().into_raw(),
+            vec![199, 254]
+        );
+    }
+
+    #[test]
+    fn decodes_jpeg_at_a_reduced_scale() {
+        let buffer =
+            std::fs::read("./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg").unwrap();
+        let size = dimensions(&buffer, ImageFormat::JPEG).unwrap();
+
+        let image = jpeg_scaled(
+            &buffer,
+            &PixelSize {
+                width: size.width / 3,
+                height: size.height / 3,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(image.width(), size.width.div_ceil(2));
+        assert_eq!(image.height(), size.height.div_ceil(2));
+
+        let image = jpeg_scaled(&buffer, &size).unwrap();
+
+        assert_eq!(image.width(), size.width);
+        assert_eq!(image.height(), size.height);
+    }
+
+    #[test]
+    fn counts_frames_without_decoding() {
+        let header = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff";
+        let extension = b"\x21\xf9\x04\x00\x00\x00\x00\x00";
+        let frame = b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00";
+
+        let mut buffer = header.to_vec();
+        for _ in 0..3 {
+            buffer.extend_from_slice(extension);
+            buffer.extend_from_slice(frame);
+        }
+        buffer.push(0x3b);
+
+        assert_eq!(frames(&buffer, ImageFormat::GIF).unwrap(), 3);
+        assert!(frames(&buffer[..20], ImageFormat::GIF).is_err());
+
+        let buffer = std::fs::read("./tests/input/test_pattern.png").unwrap();
+
+        assert_eq!(frames(&buffer, ImageFormat::PNG).unwrap(), 1);
+
+        // A chunk length that would wrap the position around on 32 bit targets
+        let mut buffer = buffer[..33].to_vec();
+        buffer.extend_from_slice(b"\xff\xff\xff\xf8tEXt");
+        buffer.extend_from_slice(&[0; 16]);
+
+        assert!(frames(&buffer, ImageFormat::PNG).is_err());
+    }
+
+    #[test]
+    fn reads_alpha_from_headers() {
+        let header = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff";
+        let frame = b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b";
+
+        let mut buffer = header.to_vec();
+        buffer.extend_from_slice(b"\x21\xf9\x04\x00\x00\x00\x00\x00");
+        buffer.extend_from_slice(frame);
+
+        assert!(!has_alpha(&buffer, ImageFormat::GIF).unwrap());
+
+        let mut buffer = header.to_vec();
+        buffer.extend_from_slice(b"\x21\xf9\x04\x01\x00\x00\x00\x00");
+        buffer.extend_from_slice(frame);
+
+        assert!(has_alpha(&buffer, ImageFormat::GIF).unwrap());
+
+        let buffer =
+            std::fs::read("./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg").unwrap();
+
+        assert!(!has_alpha(&buffer, ImageFormat::JPEG).unwrap());
+    }
+
+    #[test]
+    fn converts_cmyk_and_ycck_to_rgb() {
+        for path in [
+            "./tests/input/cmyk.jpg",
+            "./tests/input/ycck.jpg",
+            "./tests/input/cmyk_without_adobe.jpg",
+        ] {
+            let buffer = std::fs::read(path).unwrap();
+            let size = dimensions(&buffer, ImageFormat::JPEG).unwrap();
+            let image = jpeg_scaled(&buffer, &size).unwrap().to_rgb();
+
+            let red = image.get_pixel(3, 4).channels().to_vec();
+            let grey = image.get_pixel(12, 4).channels().to_vec();
+
+            assert!(
+                red[0] > 245 && red[1] < 10 && red[2] < 10,
+                "{} {:?}",
+                path,
+                red
+            );
+            for channel in grey {
+                assert!((channel as i32 - 127).abs() < 5, "{} {}", path, channel);
+            }
+        }
+    }
+
+    #[test]
+    fn converts_cmyk_with_its_profile() {
+        let icc_profile = std::fs::read("./tests/input/grey_cmyk.icc").unwrap();
+        let buffer = profile::embed(
+            std::fs::read("./tests/input/cmyk.jpg").unwrap(),
+            &image::ImageOutputFormat::JPEG(90),
+            &icc_profile,
+        )
+        .unwrap();
+        let size = dimensions(&buffer, ImageFormat::JPEG).unwrap();
+        let image = jpeg_scaled(&buffer, &size).unwrap().to_rgb();
+
+        // The profile maps every color to a mid grey
+        for pixel in image.pixels() {
+            assert_eq!(pixel.data[0], pixel.data[1]);
+            assert_eq!(pixel.data[1], pixel.data[2]);
+            assert!((pixel.data[0] as i32 - 119).abs() < 5, "{:?}", pixel.data);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use super::adjust::{luminance, map_srgb, map_srgb_at};
+use super::resample::{self, linear_to_srgb, FloatImage};
+
+/// The longest side of the copy that statistics are computed on.
+const SAMPLE_SIZE: u32 = 256;
+/// The share of the darkest and lightest pixels that levels clip.
+const LEVELS_CLIP: f32 = 0.005;
+/// How far white balance may scale a channel, either way.
+const MAX_GAIN: f32 = 2.0;
+/// The most tiles that equalization divides each side into.
+const TILES: u32 = 8;
+/// How many times the average count a histogram bin may have before the
+/// excess is spread over all bins, which limits the gain in contrast.
+const CLIP_LIMIT: f32 = 3.0;
+
+/// Automatic enhancements, made before the `Adjustments` and in the order of
+/// the fields.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Enhancements {
+    /// Scales the channels to the same average, which removes color casts.
+    pub white_balance: bool,
+    /// Stretches the luminance so that the darkest and lightest pixels become
+    /// black and white.
+    pub levels: bool,
+    /// Contrast limited adaptive histogram equalization (CLAHE) of the
+    /// luminance, which brings out detail in dull areas.
+    pub equalize: bool,
+}
+
+impl Enhancements {
+    /// Enhances premultiplied pixels, which are in linear light if `linear`.
+    /// Statistics come from a copy that is at most `SAMPLE_SIZE` pixels wide
+    /// and high, and are used for the whole image.
+    pub fn apply(&self, image: &mut FloatImage, linear: bool) {
+        if *self == Enhancements::default() || image.pixels.is_empty() {
+            return;
+        }
+
+        let mut sample = Sample::new(image, linear);
+
+        if self.white_balance {
+            if let Some(gains) = sample.white_balance_gains() {
+                let balance = move |rgb: [f32; 3]| {
+                    [
+                        (rgb[0] * gains[0]).clamp(0.0, 1.0),
+                        (rgb[1] * gains[1]).clamp(0.0, 1.0),
+                        (rgb[2] * gains[2]).clamp(0.0, 1.0),
+                    ]
+                };
+                sample.map(balance);
+                map_srgb(image, linear, balance);
+            }
+        }
+
+        if self.levels {
+            if let Some((low, high)) = sample.levels() {
+                let stretch = move |rgb: [f32; 3]| {
+                    let mut output = [0.0; 3];
+                    for (value, channel) in output.iter_mut().zip(rgb.iter()) {
+                        *value = ((channel - low) / (high - low)).clamp(0.0, 1.0);
+                    }
+                    output
+                };
+                sample.map(stretch);
+                map_srgb(image, linear, stretch);
+            }
+        }
+
+        if self.equalize {
+            let equalization = Equalization::new(&sample);
+            let scale_x = sample.width as f32 / image.width as f32;
+            let scale_y = sample.height as f32 / image.height as f32;
+
+            map_srgb_at(image, linear, |x, y, rgb| {
+                let luminance = luminance(rgb);
+                let equalized = equalization.map(
+                    (x as f32 + 0.5) * scale_x,
+                    (y as f32 + 0.5) * scale_y,
+                    luminance,
+                );
+
+                if luminance > 0.0 {
+                    let gain = equalized / luminance;
+                    [
+                        (rgb[0] * gain).clamp(0.0, 1.0),
+                        (rgb[1] * gain).clamp(0.0, 1.0),
+                        (rgb[2] * gain).clamp(0.0, 1.0),
+                    ]
+                } else {
+                    [equalized; 3]
+                }
+            });
+        }
+    }
+}
+
+/// A reduced copy of the image, with unpremultiplied sRGB values.
+struct Sample {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Sample {
+    fn new(image: &FloatImage, linear: bool) -> Self {
+        let factor = image.width.max(image.height).div_ceil(SAMPLE_SIZE).max(1);
+        let mut reduced = resample::box_reduce(image, factor);
+        reduced.unpremultiply();
+
+        if linear {
+            for pixel in reduced.pixels.iter_mut() {
+                pixel[0] = linear_to_srgb(pixel[0]);
+                pixel[1] = linear_to_srgb(pixel[1]);
+                pixel[2] = linear_to_srgb(pixel[2]);
+            }
+        }
+
+        Sample {
+            width: reduced.width,
+            height: reduced.height,
+            pixels: reduced.pixels,
+        }
+    }
+
+    fn map<F: Fn([f32; 3]) -> [f32; 3]>(&mut self, f: F) {
+        for pixel in self.pixels.iter_mut() {
+            let rgb = f([pixel[0], pixel[1], pixel[2]]);
+            pixel[..3].copy_from_slice(&rgb);
+        }
+    }
+
+    /// Gains that give each channel the average of all three, weighed by
+    /// alpha, or `None` when the sample is black or transparent.
+    fn white_balance_gains(&self) -> Option<[f32; 3]> {
+        let mut sums = [0.0_f32; 3];
+
+        for pixel in self.pixels.iter() {
+            for (sum, value) in sums.iter_mut().zip(pixel.iter()) {
+                *sum += value * pixel[3];
+            }
+        }
+
+        if sums.iter().any(|sum| *sum <= 0.0) {
+            return None;
+        }
+
+        let average = (sums[0] + sums[1] + sums[2]) / 3.0;
+
+        Some([
+            (average / sums[0]).clamp(1.0 / MAX_GAIN, MAX_GAIN),
+            (average / sums[1]).clamp(1.0 / MAX_GAIN, MAX_GAIN),
+            (average / sums[2]).clamp(1.0 / MAX_GAIN, MAX_GAIN),
+        ])
+    }
+
+    /// The luminance below and above which `LEVELS_CLIP` of the pixels are,
+    /// or `None` when there is no range to stretch.
+    fn levels(&self) -> Option<(f32, f32)> {
+        let histogram = self.histogram(0..self.width, 0..self.height);
+        let total: f32 = histogram.iter().sum();
+        let clip = total * LEVELS_CLIP;
+
+        let mut count = 0.0;
+        let low = histogram.iter().position(|bin| {
+            count += bin;
+            count > clip
+        })?;
+
+        count = 0.0;
+        let high = 255
+            - histogram.iter().rev().position(|bin| {
+                count += bin;
+                count > clip
+            })?;
+
+        if high > low {
+            Some((low as f32 / 255.0, high as f32 / 255.0))
+        } else {
+            None
+        }
+    }
+
+    /// Counts the luminance of the pixels in a region, weighed by alpha.
+    fn histogram(&self, columns: std::ops::Range<u32>, rows: std::ops::Range<u32>) -> [f32; 256] {
+        let mut histogram = [0.0; 256];
+
+        for y in rows {
+            for x in columns.clone() {
+                let pixel = self.pixels[(y * self.width + x) as usize];
+                let luminance = luminance([pixel[0], pixel[1], pixel[2]]);
+                histogram[(luminance * 255.0).round() as usize] += pixel[3];
+            }
+        }
+
+        histogram
+    }
+}
+
+/// Equalizing mappings of the luminance for a grid of tiles of a `Sample`.
+struct Equalization {
+    columns: u32,
+    rows: u32,
+    tile_width: f32,
+    tile_height: f32,
+    tables: Vec<[f32; 256]>,
+}
+
+impl Equalization {
+    fn new(sample: &Sample) -> Self {
+        let columns = TILES.min(sample.width);
+        let rows = TILES.min(sample.height);
+        let mut tables = Vec::with_capacity((columns * rows) as usize);
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let histogram = sample.histogram(
+                    column * sample.width / columns..(column + 1) * sample.width / columns,
+                    row * sample.height / rows..(row + 1) * sample.height / rows,
+                );
+                tables.push(equalize(histogram));
+            }
+        }
+
+        Equalization {
+            columns,
+            rows,
+            tile_width: sample.width as f32 / columns as f32,
+            tile_height: sample.height as f32 / rows as f32,
+            tables,
+        }
+    }
+
+    /// Maps `luminance` at `x`, `y` of the sample, interpolating between the
+    /// tables of the nearest tile centers.
+    fn map(&self, x: f32, y: f32, luminance: f32) -> f32 {
+        let bin = (luminance.clamp(0.0, 1.0) * 255.0).round() as usize;
+        let (left, right, x_weight) = neighbours(x / self.tile_width - 0.5, self.columns);
+        let (top, bottom, y_weight) = neighbours(y / self.tile_height - 0.5, self.rows);
+        let at = |column: u32, row: u32| self.tables[(row * self.columns + column) as usize][bin];
+
+        let upper = at(left, top) + (at(right, top) - at(left, top)) * x_weight;
+        let lower = at(left, bottom) + (at(right, bottom) - at(left, bottom)) * x_weight;
+
+        upper + (lower - upper) * y_weight
+    }
+}
+
+/// The tiles on either side of a position in tiles, and the weight of the
+/// second one.
+fn neighbours(position: f32, count: u32) -> (u32, u32, f32) {
+    let position = position.clamp(0.0, (count - 1) as f32);
+    let first = position.floor() as u32;
+
+    (first, (first + 1).min(count - 1), position - first as f32)
+}
+
+/// A table from luminance bins to the share of pixels at or below them, after
+/// clipping the bins at `CLIP_LIMIT`.
+fn equalize(mut histogram: [f32; 256]) -> [f32; 256] {
+    let total: f32 = histogram.iter().sum();
+    let mut table = [0.0; 256];
+
+    if total <= 0.0 {
+        for (i, value) in table.iter_mut().enumerate() {
+            *value = i as f32 / 255.0;
+        }
+        return table;
+    }
+
+    let limit = CLIP_LIMIT * total / 256.0;
+    let mut excess = 0.0;
+    for bin in histogram.iter_mut() {
+        if *bin > limit {
+            excess += *bin - limit;
+            *bin = limit;
+        }
+    }
+
+    let mut count = 0.0;
+    for (value, bin) in table.iter_mut().zip(histogram.iter()) {
+        count += bin + excess / 256.0;
+        *value = (count / total).clamp(0.0, 1.0);
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::Enhancements;
+    use crate::image::resample::FloatImage;
+
+    fn enhance(enhancements: Enhancements, image: &image::RgbaImage, linear: bool) -> Vec<u8> {
+        let mut image = if linear {
+            FloatImage::from_rgba_linear(image)
+        } else {
+            FloatImage::from_rgba(image)
+        };
+        image.premultiply();
+        enhancements.apply(&mut image, linear);
+        image.unpremultiply();
+        if linear {
+            image.linear_to_srgb();
+        }
+
+        image.to_rgba().into_raw()
+    }
+
+    /// A dull gradient from 100 to 150 with a blue cast, and transparent
+    /// pixels in the last row.
+    fn dull() -> image::RgbaImage {
+        image::RgbaImage::from_fn(600, 20, |x, y| {
+            let value = 100 + (x / 12) as u8;
+            image::Rgba {
+                data: if y < 19 {
+                    [value, value, value + 40, 255]
+                } else {
+                    [0, 0, 0, 0]
+                },
+            }
+        })
+    }
+
+    fn channel_range(pixels: &[u8], channel: usize) -> (u8, u8) {
+        let visible = pixels.chunks(4).filter(|pixel| pixel[3] > 0);
+        let values: Vec<u8> = visible.map(|pixel| pixel[channel]).collect();
+
+        (*values.iter().min().unwrap(), *values.iter().max().unwrap())
+    }
+
+    fn channel_mean(pixels: &[u8], channel: usize) -> f32 {
+        let visible: Vec<&[u8]> = pixels.chunks(4).filter(|pixel| pixel[3] > 0).collect();
+        let sum: f32 = visible.iter().map(|pixel| f32::from(pixel[channel])).sum();
+
+        sum / visible.len() as f32
+    }
+
+    #[test]
+    fn changes_nothing_by_default() {
+        let image = dull();
+
+        assert!(enhance(Enhancements::default(), &image, false) == image.into_raw());
+    }
+
+    #[test]
+    fn balances_white() {
+        let enhancements = Enhancements {
+            white_balance: true,
+            ..Enhancements::default()
+        };
+
+        for linear in [false, true] {
+            let output = enhance(enhancements, &dull(), linear);
+            let means: Vec<f32> = (0..3)
+                .map(|channel| channel_mean(&output, channel))
+                .collect();
+
+            assert!((means[0] - means[2]).abs() < 1.0, "{:?}", means);
+            assert!((means[1] - means[2]).abs() < 1.0, "{:?}", means);
+            assert_eq!(&output[output.len() - 4..], &[0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn stretches_levels() {
+        let enhancements = Enhancements {
+            white_balance: true,
+            levels: true,
+            ..Enhancements::default()
+        };
+        let output = enhance(enhancements, &dull(), false);
+
+        let (low, high) = channel_range(&output, 1);
+        assert!(low < 5 && high > 250, "{} to {}", low, high);
+    }
+
+    #[test]
+    fn equalizes_histograms() {
+        let enhancements = Enhancements {
+            equalize: true,
+            ..Enhancements::default()
+        };
+        let image = dull();
+        let output = enhance(enhancements, &image, false);
+
+        let (input_low, input_high) = channel_range(&image.into_raw(), 0);
+        let (low, high) = channel_range(&output, 0);
+        assert!(
+            high - low > input_high - input_low + 10,
+            "{} to {}",
+            low,
+            high
+        );
+
+        // The gradient stays in order along each row
+        let row: Vec<u8> = output.chunks(4).take(600).map(|pixel| pixel[0]).collect();
+        assert!(row.windows(2).all(|pair| pair[0] <= pair[1] + 2));
+    }
+}
@@ -0,0 +1,150 @@
+//! The arithmetic on premultiplied `[f32; 4]` pixels that resampling and
+//! compositing spend their time in.
+//!
+//! Both variants multiply and then add each channel separately, so they
+//! round identically and produce the same output.
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))] {
+        pub use self::simd::{accumulate, accumulate_row, accumulate_weighted, scale};
+    } else {
+        pub use self::scalar::{accumulate, accumulate_row, accumulate_weighted, scale};
+    }
+}
+
+#[cfg(any(
+    test,
+    not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))
+))]
+mod scalar {
+    pub fn accumulate(sum: &mut [f32; 4], pixel: &[f32; 4], weight: f32) {
+        sum[0] += pixel[0] * weight;
+        sum[1] += pixel[1] * weight;
+        sum[2] += pixel[2] * weight;
+        sum[3] += pixel[3] * weight;
+    }
+
+    /// Adds `pixels`, each weighed by its weight times `factor`, to `sum`.
+    pub fn accumulate_weighted(
+        sum: &mut [f32; 4],
+        pixels: &[[f32; 4]],
+        weights: &[f32],
+        factor: f32,
+    ) {
+        for (pixel, weight) in pixels.iter().zip(weights.iter()) {
+            accumulate(sum, pixel, weight * factor);
+        }
+    }
+
+    /// Adds `row` weighed by `weight` to the pixels of `sums`.
+    pub fn accumulate_row(sums: &mut [[f32; 4]], row: &[[f32; 4]], weight: f32) {
+        for (sum, pixel) in sums.iter_mut().zip(row.iter()) {
+            accumulate(sum, pixel, weight);
+        }
+    }
+
+    pub fn scale(pixel: &mut [f32; 4], factor: f32) {
+        pixel[0] *= factor;
+        pixel[1] *= factor;
+        pixel[2] *= factor;
+        pixel[3] *= factor;
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod simd {
+    use std::arch::wasm32::{f32x4_add, f32x4_mul, f32x4_splat, v128, v128_load, v128_store};
+
+    // A pixel is 16 contiguous bytes, which `v128_load` and `v128_store`
+    // read and write in one go without needing them aligned
+    fn load(pixel: &[f32; 4]) -> v128 {
+        unsafe { v128_load(pixel.as_ptr() as *const v128) }
+    }
+
+    fn store(pixel: &mut [f32; 4], value: v128) {
+        unsafe { v128_store(pixel.as_mut_ptr() as *mut v128, value) }
+    }
+
+    pub fn accumulate(sum: &mut [f32; 4], pixel: &[f32; 4], weight: f32) {
+        store(
+            sum,
+            f32x4_add(load(sum), f32x4_mul(load(pixel), f32x4_splat(weight))),
+        );
+    }
+
+    /// Keeps the sum in a register across all the taps.
+    pub fn accumulate_weighted(
+        sum: &mut [f32; 4],
+        pixels: &[[f32; 4]],
+        weights: &[f32],
+        factor: f32,
+    ) {
+        let mut total = load(sum);
+
+        for (pixel, weight) in pixels.iter().zip(weights.iter()) {
+            total = f32x4_add(total, f32x4_mul(load(pixel), f32x4_splat(weight * factor)));
+        }
+
+        store(sum, total);
+    }
+
+    pub fn accumulate_row(sums: &mut [[f32; 4]], row: &[[f32; 4]], weight: f32) {
+        let weight = f32x4_splat(weight);
+
+        for (sum, pixel) in sums.iter_mut().zip(row.iter()) {
+            store(sum, f32x4_add(load(sum), f32x4_mul(load(pixel), weight)));
+        }
+    }
+
+    pub fn scale(pixel: &mut [f32; 4], factor: f32) {
+        store(pixel, f32x4_mul(load(pixel), f32x4_splat(factor)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::scalar;
+
+    fn pixels(count: usize, seed: usize) -> Vec<[f32; 4]> {
+        (0..count)
+            .map(|i| {
+                let value = |channel: usize| ((i * 4 + channel) * 37 + seed) % 101;
+                [
+                    value(0) as f32 / 97.0,
+                    value(1) as f32 / 89.0,
+                    value(2) as f32 / 83.0,
+                    value(3) as f32 / 101.0,
+                ]
+            })
+            .collect()
+    }
+
+    /// Compares the kernels that the build uses, which are the simd ones in
+    /// `wasm-pack test` builds with the `simd` feature, to the scalar ones.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn match_the_scalar_kernels() {
+        let row = pixels(37, 5);
+        let weights: Vec<f32> = (0..37).map(|i| (i as f32 - 12.5) / 61.0).collect();
+
+        let mut expected = pixels(37, 11);
+        let mut actual = expected.clone();
+        scalar::accumulate_row(&mut expected, &row, 0.3711);
+        super::accumulate_row(&mut actual, &row, 0.3711);
+        assert_eq!(actual, expected);
+
+        for (i, pixel) in expected.iter_mut().enumerate() {
+            scalar::accumulate_weighted(pixel, &row[i..], &weights[i..], 1.7);
+            scalar::accumulate(pixel, &row[i], -0.25);
+            scalar::scale(pixel, 0.913);
+        }
+        for (i, pixel) in actual.iter_mut().enumerate() {
+            super::accumulate_weighted(pixel, &row[i..], &weights[i..], 1.7);
+            super::accumulate(pixel, &row[i], -0.25);
+            super::scale(pixel, 0.913);
+        }
+        assert_eq!(actual, expected);
+    }
+}
@@ -0,0 +1,166 @@
+use super::retarget::Plan;
+use super::transform::{PixelSize, Transform};
+
+use failure::Fail;
+use std::fmt;
+
+/// Caps on how much a request may decode and produce. They are checked from
+/// the image headers and the transform, before any pixels are decoded.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Limits {
+    pub bytes: u64,
+    pub frames: u64,
+    pub input_pixels: u64,
+    pub output_pixels: u64,
+    /// Pixels visited while finding seams in retarget mode, see
+    /// `Plan::seam_pixels`.
+    pub seam_pixels: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            bytes: 20 * 1024 * 1024,
+            frames: 100,
+            input_pixels: 50_000_000,
+            output_pixels: 4096 * 4096,
+            seam_pixels: 1_000_000_000,
+        }
+    }
+}
+
+/// Returned when an image, or the canvas it is placed on, exceeds `Limits`.
+#[derive(PartialEq, Debug)]
+pub struct TooLarge {
+    pub what: &'static str,
+    pub size: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "too large: {} of {} exceeds the limit of {}",
+            self.what, self.size, self.limit
+        )
+    }
+}
+
+impl Fail for TooLarge {}
+
+impl Limits {
+    pub fn check_bytes(&self, bytes: usize) -> Result<(), TooLarge> {
+        check("input bytes", bytes as u64, self.bytes)
+    }
+
+    pub fn check_frames(&self, frames: u64) -> Result<(), TooLarge> {
+        check("frames", frames, self.frames)
+    }
+
+    pub fn check_input(&self, size: &PixelSize) -> Result<(), TooLarge> {
+        check("input pixels", pixels(size), self.input_pixels)
+    }
+
+    /// Covers the canvas size computed from the requested width and height,
+    /// and the seams that retargeting to it would carve.
+    pub fn check_output(&self, transform: &Transform) -> Result<(), TooLarge> {
+        let canvas = transform.get_output_pixel_dimensions().canvas;
+
+        check("output pixels", pixels(&canvas), self.output_pixels)?;
+
+        match transform.get_retarget_size() {
+            Some(size) => check(
+                "seam pixels",
+                Plan::new(&transform.get_decode_size(), &size).seam_pixels(),
+                self.seam_pixels,
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+fn pixels(size: &PixelSize) -> u64 {
+    u64::from(size.width) * u64::from(size.height)
+}
+
+fn check(what: &'static str, size: u64, limit: u64) -> Result<(), TooLarge> {
+    if size > limit {
+        Err(TooLarge { what, size, limit })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Limits, TooLarge};
+    use crate::image::transform::{PixelSize, Transform, TransformMode};
+
+    #[test]
+    fn rejects_what_exceeds_the_limits() {
+        let limits = Limits::default();
+
+        assert!(limits.check_bytes(1024).is_ok());
+        assert!(limits
+            .check_input(&PixelSize {
+                width: 6000,
+                height: 4000,
+            })
+            .is_ok());
+        assert_eq!(
+            limits.check_input(&PixelSize {
+                width: 60000,
+                height: 60000,
+            }),
+            Err(TooLarge {
+                what: "input pixels",
+                size: 3_600_000_000,
+                limit: 50_000_000,
+            })
+        );
+
+        let transform = Transform::new(
+            &PixelSize {
+                width: 100,
+                height: 100,
+            },
+            TransformMode::Fit {
+                width: 100_000,
+                height: 100_000,
+            },
+        );
+
+        assert_eq!(
+            limits.check_output(&transform).unwrap_err().to_string(),
+            "too large: output pixels of 10000000000 exceeds the limit of 16777216"
+        );
+    }
+
+    #[test]
+    fn rejects_retargeting_that_carves_too_many_seams() {
+        let limits = Limits::default();
+        let source = PixelSize {
+            width: 4000,
+            height: 3000,
+        };
+
+        let banner = Transform::new(
+            &source,
+            TransformMode::Retarget {
+                width: 1200,
+                height: 400,
+            },
+        );
+        assert!(limits.check_output(&banner).is_ok());
+
+        let strip = Transform::new(
+            &source,
+            TransformMode::Retarget {
+                width: 4000,
+                height: 100,
+            },
+        );
+        assert_eq!(limits.check_output(&strip).unwrap_err().what, "seam pixels");
+    }
+}
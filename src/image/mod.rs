@@ -1,13 +1,37 @@
+mod adjust;
+mod affine;
+pub mod budget;
+mod decode;
+mod enhance;
+mod kernels;
+mod limits;
+pub mod palette;
+mod parallel;
+mod profile;
+mod resample;
+mod retarget;
+pub mod stats;
+mod stream;
 mod transform;
 
-pub use image::ImageOutputFormat;
+pub use image::{ImageFormat, ImageOutputFormat};
 
-use image::{
-    guess_format, load_from_memory, DynamicImage, FilterType, GenericImage, GenericImageView,
-    ImageFormat,
-};
+use image::{guess_format, load_from_memory, DynamicImage, GenericImageView};
+
+use affine::Affine;
+use profile::SrgbRows;
+use resample::{FloatImage, Reduction, Window};
+use stream::{ImageRows, PngRows, Rows};
+
+pub use adjust::{Adjustments, Effect};
+pub use budget::Degradations;
+pub use enhance::Enhancements;
+pub use limits::{Limits, TooLarge};
+pub use resample::Filter;
 
-pub use transform::{PixelCoords, PixelSize, Transform, TransformMode};
+pub use transform::{
+    Flip, Length, PixelDimensions, PixelSize, Region, Rotation, Transform, TransformMode,
+};
 
 pub fn input_to_output_format(
     input_format: ImageFormat,
@@ -27,9 +51,100 @@ pub fn input_format(buffer: &[u8]) -> Result<ImageFormat, failure::Error> {
 }
 
 pub fn load(buffer: &[u8]) -> Result<DynamicImage, failure::Error> {
+    if decode::is_png_16(buffer) {
+        return decode::png_16(buffer)
+            .map_err(|e| failure::format_err!("could not load image {}", e));
+    }
+
     load_from_memory(buffer).map_err(|e| failure::format_err!("could not load image {}", e))
 }
 
+pub fn input_size(buffer: &[u8]) -> Result<PixelSize, failure::Error> {
+    decode::dimensions(buffer, input_format(buffer)?)
+        .map_err(|e| failure::format_err!("could not read image size {}", e))
+}
+
+/// Reads the input size like `input_size`, after checking the input against
+/// `limits` without decoding any pixels.
+pub fn check_input(buffer: &[u8], limits: &Limits) -> Result<PixelSize, failure::Error> {
+    limits.check_bytes(buffer.len())?;
+
+    let format = input_format(buffer)?;
+    let size = decode::dimensions(buffer, format)
+        .map_err(|e| failure::format_err!("could not read image size {}", e))?;
+    limits.check_input(&size)?;
+
+    let frames = decode::frames(buffer, format)
+        .map_err(|e| failure::format_err!("could not read image frames {}", e))?;
+    limits.check_frames(frames)?;
+
+    Ok(size)
+}
+
+/// What the headers of an image tell about it.
+#[derive(PartialEq, Debug)]
+pub struct Probe {
+    pub format: ImageFormat,
+    pub size: PixelSize,
+    pub alpha: bool,
+    pub frames: u64,
+}
+
+/// Reads the format, size, alpha and frame count without decoding any pixels.
+pub fn probe(buffer: &[u8]) -> Result<Probe, failure::Error> {
+    let format = input_format(buffer)?;
+    let size = decode::dimensions(buffer, format)
+        .map_err(|e| failure::format_err!("could not read image size {}", e))?;
+    let alpha = decode::has_alpha(buffer, format)
+        .map_err(|e| failure::format_err!("could not read image alpha {}", e))?;
+    let frames = decode::frames(buffer, format)
+        .map_err(|e| failure::format_err!("could not read image frames {}", e))?;
+
+    Ok(Probe {
+        format,
+        size,
+        alpha,
+        frames,
+    })
+}
+
+/// Loads the image at the lowest resolution that `transform` needs, which
+/// saves decoding most of the pixels of large JPEGs, and converts it to sRGB
+/// using its embedded ICC profile.
+pub fn load_for(buffer: &[u8], transform: &Transform) -> Result<DynamicImage, failure::Error> {
+    convert_to_srgb(buffer, load_scaled(buffer, &transform.get_decode_size())?)
+}
+
+/// Loads the whole image, converted to sRGB like `load_for`, which also
+/// brings CMYK and YCCK JPEGs to RGB.
+pub fn load_srgb(buffer: &[u8]) -> Result<DynamicImage, failure::Error> {
+    convert_to_srgb(buffer, load_scaled(buffer, &input_size(buffer)?)?)
+}
+
+fn convert_to_srgb(buffer: &[u8], image: DynamicImage) -> Result<DynamicImage, failure::Error> {
+    match read_profile(buffer, input_format(buffer)?)? {
+        Some(icc_profile) => match profile::to_srgb(&icc_profile) {
+            Some(srgb) => Ok(profile::convert(image, &srgb)),
+            None => Ok(image),
+        },
+        None => Ok(image),
+    }
+}
+
+fn read_profile(buffer: &[u8], format: ImageFormat) -> Result<Option<Vec<u8>>, failure::Error> {
+    profile::read(buffer, format)
+        .map_err(|e| failure::format_err!("could not read image profile {}", e))
+}
+
+/// Loads JPEGs at the lowest resolution that is still at least `target`.
+fn load_scaled(buffer: &[u8], target: &PixelSize) -> Result<DynamicImage, failure::Error> {
+    match input_format(buffer)? {
+        ImageFormat::JPEG => decode::jpeg_scaled(buffer, target)
+            .map_err(|e| failure::format_err!("could not load image {}", e)),
+        _ => load(buffer),
+    }
+}
+
 pub fn size(image: &DynamicImage) -> PixelSize {
     PixelSize {
         width: image.width(),
@@ -37,66 +152,340 @@ pub fn size(image: &DynamicImage) -> PixelSize {
     }
 }
 
+/// How source pixels are resampled onto the canvas.
+#[derive(Default)]
+pub struct Sampling {
+    /// Chosen from the resize direction when not given.
+    pub filter: Option<Filter>,
+    /// Resample in linear light rather than sRGB.
+    pub linear: bool,
+}
+
+/// Changes to the colors of the resampled image before it is encoded.
+#[derive(Default, Clone)]
+pub struct Colors {
+    pub enhancements: Enhancements,
+    pub adjustments: Adjustments,
+    /// Made after the adjustments.
+    pub effect: Option<Effect>,
+    /// Composited under the adjusted image.
+    pub background: Option<Background>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Background {
+    /// With straight alpha.
+    Color([u8; 4]),
+    /// The most common color of the image, from `palette::dominant`.
+    Dominant,
+}
+
+impl Colors {
+    pub fn background(color: [u8; 4]) -> Self {
+        Colors {
+            background: Some(Background::Color(color)),
+            ..Colors::default()
+        }
+    }
+}
+
 pub fn process(
     image: &mut DynamicImage,
     transform: &Transform,
+    sampling: &Sampling,
+    output_format: ImageOutputFormat,
+    colors: &Colors,
+) -> Result<Vec<u8>, failure::Error> {
+    process_degraded(
+        image,
+        transform,
+        sampling,
+        output_format,
+        colors,
+        Degradations::default(),
+        false,
+    )
+}
+
+fn process_degraded(
+    image: &mut DynamicImage,
+    transform: &Transform,
+    sampling: &Sampling,
     output_format: ImageOutputFormat,
-    color: Option<[u8; 3]>,
+    colors: &Colors,
+    degradations: Degradations,
+    keep_rgb: bool,
 ) -> Result<Vec<u8>, failure::Error> {
-    let output_dimensions = transform.get_output_pixel_dimensions();
-    let canvas_size = output_dimensions.canvas;
-    let output_size = output_dimensions.size;
-    let output_origin = output_dimensions.origin;
+    let canvas_size = transform.get_output_pixel_dimensions().canvas;
 
-    if color.is_some() {
-        fill(image, color.unwrap());
+    if canvas_size.width == 0 || canvas_size.height == 0 {
+        return Err(failure::format_err!(
+            "could not place image due to sizing errors",
+        ));
     }
 
-    let mut resized_image =
-        image.resize_exact(output_size.width, output_size.height, FilterType::Triangle);
+    let source_affine = transform.get_affine_for(&size(image));
+    let filter_for = |affine: &Affine| {
+        sampling
+            .filter
+            .unwrap_or_else(|| Filter::for_affine(affine))
+    };
 
-    let mut output_canvas = DynamicImage::new_rgba8(canvas_size.width, canvas_size.height);
+    let (source, bounds, affine) = match transform.get_retarget_size() {
+        Some(retarget_size) => {
+            let source = to_float(image, sampling.linear);
+            let carved = retarget::retarget(&source, &retarget_size, &transform.protected_regions);
+            let affine = Affine::scale(
+                source.width as f32 / carved.width as f32,
+                source.height as f32 / carved.height as f32,
+            )
+            .then(&source_affine);
 
-    let sub_image_x: u32;
-    let sub_image_y: u32;
-    let copied_x: u32;
-    let copied_y: u32;
+            (carved, None, affine)
+        }
+        None => {
+            // Very large reductions first average whole blocks of pixels, and
+            // only the part of the source that lands on the canvas is converted
+            // and resampled, which matters for large scales and tight crops
+            let reduction = Reduction::new(
+                image.width(),
+                image.height(),
+                &source_affine,
+                canvas_size.width,
+                canvas_size.height,
+                filter_for(&source_affine),
+            );
+            let visible = reduction.source_bounds();
 
-    if output_origin.x < 0 {
-        sub_image_x = output_origin.x.abs() as u32;
-        copied_x = 0;
-    } else {
-        sub_image_x = 0;
-        copied_x = output_origin.x as u32;
-    }
+            let source = if (visible.width, visible.height) == image.dimensions() {
+                to_float(image, sampling.linear)
+            } else {
+                let visible = image.crop(visible.left, visible.top, visible.width, visible.height);
+                to_float(&visible, sampling.linear)
+            };
 
-    if output_origin.y < 0 {
-        sub_image_y = output_origin.y.abs() as u32;
-        copied_y = 0;
-    } else {
-        sub_image_y = 0;
-        copied_y = output_origin.y as u32;
-    }
-
-    let has_copied = output_canvas.copy_from(
-        &resized_image.sub_image(
-            sub_image_x,
-            sub_image_y,
-            canvas_size.width.min(output_size.width - sub_image_x),
-            canvas_size.height.min(output_size.height - sub_image_y),
-        ),
-        copied_x,
-        copied_y,
+            let source = if reduction.factor > 1 {
+                resample::box_reduce(&source, reduction.factor)
+            } else {
+                source
+            };
+
+            (
+                source,
+                Some((reduction.bounds, reduction.width, reduction.height)),
+                reduction.affine,
+            )
+        }
+    };
+
+    let window = match bounds {
+        Some((bounds, source_width, source_height)) => Window {
+            image: &source,
+            bounds,
+            source_width,
+            source_height,
+        },
+        None => Window::whole(&source),
+    };
+
+    let canvas = resample::resample_window(
+        &window,
+        &affine,
+        canvas_size.width,
+        canvas_size.height,
+        filter_for(&affine),
     );
 
-    if !has_copied {
-        return Err(failure::format_err!(
-            "could not place image due to sizing errors",
-        ));
+    encode(
+        canvas,
+        sampling,
+        output_format,
+        colors,
+        degradations,
+        keep_rgb,
+    )
+}
+
+/// Like `load_for` followed by `process`, but passing the rows of PNG and JPEG
+/// sources straight through resampling where the transform allows it, instead
+/// of holding the whole source in memory. `degradations` come from
+/// `budget::degrade`, which also adjusts `sampling`. With `keep_profile`, the
+/// pixels are left in the color space of the embedded ICC profile, which is
+/// embedded in the output instead of converting to sRGB.
+pub fn process_buffer(
+    buffer: &[u8],
+    transform: &Transform,
+    sampling: &Sampling,
+    output_format: ImageOutputFormat,
+    colors: &Colors,
+    degradations: Degradations,
+    keep_profile: bool,
+) -> Result<Vec<u8>, failure::Error> {
+    let format = input_format(buffer)?;
+    let decode_size = degradations.decode_size(transform);
+    let canvas_size = transform.get_output_pixel_dimensions().canvas;
+
+    let icc_profile = read_profile(buffer, format)?;
+    let srgb = match &icc_profile {
+        Some(icc_profile) if !keep_profile => profile::to_srgb(icc_profile),
+        _ => None,
+    };
+    // CMYK sources are converted to RGB while decoding, whatever the option
+    let embedded = icc_profile.filter(|icc_profile| keep_profile && profile::is_rgb(icc_profile));
+
+    // Seam carving and quarter turns need the whole source at once
+    let streamable =
+        transform.get_retarget_size().is_none() && transform.get_affine().is_axis_aligned();
+
+    let rows: Option<Box<dyn Rows>> = match format {
+        _ if !streamable || canvas_size.width == 0 || canvas_size.height == 0 => None,
+        ImageFormat::PNG => PngRows::new(buffer)
+            .map_err(|e| failure::format_err!("could not load image {}", e))?
+            .map(|rows| Box::new(rows) as Box<dyn Rows>),
+        ImageFormat::JPEG => Some(Box::new(ImageRows::new(load_scaled(buffer, &decode_size)?))),
+        _ => None,
+    };
+
+    let output = match rows {
+        Some(rows) => {
+            let mut rows = match srgb {
+                Some(srgb) => Box::new(SrgbRows::new(rows, srgb)),
+                None => rows,
+            };
+
+            let affine = transform.get_affine_for(&rows.size());
+            let filter = sampling
+                .filter
+                .unwrap_or_else(|| Filter::for_affine(&affine));
+
+            let canvas = stream::resample_rows(
+                rows.as_mut(),
+                &affine,
+                canvas_size.width,
+                canvas_size.height,
+                filter,
+                sampling.linear,
+            )
+            .map_err(|e| failure::format_err!("could not load image {}", e))?;
+
+            encode(
+                canvas,
+                sampling,
+                output_format.clone(),
+                colors,
+                degradations,
+                embedded.is_some(),
+            )?
+        }
+        None => {
+            let mut image = load_scaled(buffer, &decode_size)?;
+            if let Some(srgb) = srgb {
+                image = profile::convert(image, &srgb);
+            }
+
+            process_degraded(
+                &mut image,
+                transform,
+                sampling,
+                output_format.clone(),
+                colors,
+                degradations,
+                embedded.is_some(),
+            )?
+        }
+    };
+
+    match embedded {
+        Some(icc_profile) => profile::embed(output, &output_format, &icc_profile),
+        None => Ok(output),
     }
+}
 
-    if color.is_some() {
-        fill(&mut output_canvas, color.unwrap());
+/// One of several outputs of the same source, made by `process_batch`.
+pub struct Job {
+    pub transform: Transform,
+    pub sampling: Sampling,
+    pub output_format: ImageOutputFormat,
+    pub colors: Colors,
+    pub keep_profile: bool,
+}
+
+/// Runs `process_buffer` for each job, on a thread pool with the `parallel`
+/// feature, returning the outputs in the order of `jobs`.
+pub fn process_batch(buffer: &[u8], jobs: &[Job]) -> Vec<Result<Vec<u8>, failure::Error>> {
+    parallel::map(jobs, |job| {
+        process_buffer(
+            buffer,
+            &job.transform,
+            &job.sampling,
+            job.output_format.clone(),
+            &job.colors,
+            Degradations::default(),
+            job.keep_profile,
+        )
+    })
+}
+
+/// Enhances and adjusts the premultiplied canvas, composites it over the
+/// background, if any, and encodes it, with a single channel if an effect left
+/// it grey. With `keep_rgb` it keeps three channels, which an RGB profile that
+/// is embedded afterwards needs.
+fn encode(
+    mut canvas: FloatImage,
+    sampling: &Sampling,
+    output_format: ImageOutputFormat,
+    colors: &Colors,
+    degradations: Degradations,
+    keep_rgb: bool,
+) -> Result<Vec<u8>, failure::Error> {
+    colors.enhancements.apply(&mut canvas, sampling.linear);
+    colors.adjustments.apply(&mut canvas, sampling.linear);
+    if let Some(effect) = colors.effect {
+        effect.apply(&mut canvas, sampling.linear);
+    }
+
+    let background = match colors.background {
+        Some(Background::Color(color)) => Some(color),
+        Some(Background::Dominant) => palette::dominant(&canvas, sampling.linear)
+            .map(|color| [color[0], color[1], color[2], 255]),
+        None => None,
+    };
+
+    if let Some(color) = background {
+        let mut color = [
+            f32::from(color[0]) / 255.0,
+            f32::from(color[1]) / 255.0,
+            f32::from(color[2]) / 255.0,
+            f32::from(color[3]) / 255.0,
+        ];
+
+        if sampling.linear {
+            for channel in color[..3].iter_mut() {
+                *channel = resample::srgb_to_linear(*channel);
+            }
+        }
+
+        canvas.fill_premultiplied(color);
+    }
+
+    canvas.unpremultiply();
+
+    if sampling.linear {
+        canvas.linear_to_srgb();
+    }
+
+    let rgba = canvas.to_rgba();
+    let output_canvas = match colors.effect {
+        Some(_) if !keep_rgb && rgba.pixels().all(|pixel| is_grey(pixel.data)) => {
+            let alpha = output_format == ImageOutputFormat::PNG
+                && rgba.pixels().any(|pixel| pixel.data[3] < 255);
+            to_grey(rgba, alpha)
+        }
+        _ => DynamicImage::ImageRgba8(rgba),
+    };
+
+    if let (ImageOutputFormat::PNG, true) = (&output_format, degradations.encode) {
+        return encode_fast_png(&output_canvas);
     }
 
     let mut output: Vec<u8> = Vec::new();
@@ -106,29 +495,68 @@ pub fn process(
         .map_err(|_| failure::format_err!("um"))
 }
 
-fn fill(image: &mut DynamicImage, color_data: [u8; 3]) {
-    match image {
-        DynamicImage::ImageRgba8(image_buffer) => {
-            for mut pixel_mut in image_buffer.pixels_mut() {
-                let a = pixel_mut.data[3] as f32 / 255.0;
+fn is_grey(pixel: [u8; 4]) -> bool {
+    pixel[0] == pixel[1] && pixel[1] == pixel[2]
+}
+
+/// Keeps the first channel of grey pixels, and alpha if `alpha`.
+fn to_grey(rgba: image::RgbaImage, alpha: bool) -> DynamicImage {
+    let (width, height) = rgba.dimensions();
+    let pixels = rgba.into_raw();
 
-                let r = (a * pixel_mut.data[0] as f32) + ((1.0 - a) * color_data[0] as f32);
-                let g = (a * pixel_mut.data[1] as f32) + ((1.0 - a) * color_data[1] as f32);
-                let b = (a * pixel_mut.data[2] as f32) + ((1.0 - a) * color_data[2] as f32);
+    if alpha {
+        let pixels = pixels
+            .chunks(4)
+            .flat_map(|pixel| [pixel[0], pixel[3]])
+            .collect();
+        DynamicImage::ImageLumaA8(image::ImageBuffer::from_raw(width, height, pixels).unwrap())
+    } else {
+        let pixels = pixels.chunks(4).map(|pixel| pixel[0]).collect();
+        DynamicImage::ImageLuma8(image::GrayImage::from_raw(width, height, pixels).unwrap())
+    }
+}
 
-                pixel_mut.data[0] = r as u8;
-                pixel_mut.data[1] = g as u8;
-                pixel_mut.data[2] = b as u8;
-                pixel_mut.data[3] = 255;
-            }
-        }
-        _ => {}
+/// Encodes with the fastest deflate setting, which `write_to` does not offer.
+fn encode_fast_png(image: &DynamicImage) -> Result<Vec<u8>, failure::Error> {
+    use png::HasParameters;
+
+    let color_type = match image.color() {
+        image::ColorType::Gray(_) => png::ColorType::Grayscale,
+        image::ColorType::GrayA(_) => png::ColorType::GrayscaleAlpha,
+        _ => png::ColorType::RGBA,
     };
+
+    let mut output: Vec<u8> = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut output, image.width(), image.height());
+        encoder
+            .set(color_type)
+            .set(png::BitDepth::Eight)
+            .set(png::Compression::Fast);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&image.raw_pixels())?;
+    }
+
+    Ok(output)
+}
+
+/// Converts to premultiplied float pixels, in linear light if `linear`.
+fn to_float(image: &DynamicImage, linear: bool) -> FloatImage {
+    let mut source = if linear {
+        FloatImage::from_rgba_linear(&image.to_rgba())
+    } else {
+        FloatImage::from_rgba(&image.to_rgba())
+    };
+    source.premultiply();
+
+    source
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use image::ColorType;
     use std::io::prelude::*;
 
     #[test]
@@ -152,8 +580,9 @@ mod test {
         let output = process(
             &mut image,
             &transform,
+            &Sampling::default(),
             ImageOutputFormat::PNG,
-            Some([100, 200, 100]),
+            &Colors::background([100, 200, 100, 255]),
         );
 
         let mut file =
@@ -162,6 +591,29 @@ mod test {
         result.unwrap();
     }
 
+    #[test]
+    fn enlarge_a_png_image_with_nearest() {
+        let mut image = image::open(std::path::Path::new("./tests/input/simple.png")).unwrap();
+        let image_size = size(&image);
+
+        let transform = Transform::new(&image_size, TransformMode::FitWidth(image_size.width * 3));
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling {
+                filter: Some(Filter::Nearest),
+                linear: false,
+            },
+            ImageOutputFormat::PNG,
+            &Colors::default(),
+        );
+
+        let mut file = std::fs::File::create("tests/output/simple_nearest.png").unwrap();
+        let result = file.write_all(&output.unwrap());
+        result.unwrap();
+    }
+
     #[test]
     fn output_a_jpg_image() {
         let mut image =
@@ -181,8 +633,12 @@ mod test {
         let output = process(
             &mut image,
             &transform,
+            &Sampling {
+                filter: None,
+                linear: true,
+            },
             ImageOutputFormat::JPEG(90),
-            Some([100, 200, 100]),
+            &Colors::background([100, 200, 100, 255]),
         );
 
         let mut file = std::fs::File::create("tests/output/test_pattern_fit.jpg").unwrap();
@@ -191,13 +647,143 @@ mod test {
     }
 
     #[test]
-    fn process_a_jpg_image() {
+    fn retarget_a_jpg_image() {
         let mut image = image::open(std::path::Path::new(
             "./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg",
         ))
         .unwrap();
         let image_size = size(&image);
 
+        let mut transform = Transform::new(
+            &image_size,
+            TransformMode::Retarget {
+                width: 200,
+                height: 100,
+            },
+        );
+
+        transform.protected_regions = vec![Region {
+            x: 0.25,
+            y: 0.25,
+            width: 0.5,
+            height: 0.5,
+        }];
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::JPEG(90),
+            &Colors::default(),
+        );
+
+        let mut file =
+            std::fs::File::create("tests/output/Apollo_17_Image_Of_Earth_From_Space_retarget.jpg")
+                .unwrap();
+        let result = file.write_all(&output.unwrap());
+        result.unwrap();
+    }
+
+    #[test]
+    fn probe_a_png_image() {
+        let buffer = std::fs::read("./tests/input/test_pattern.png").unwrap();
+        let image = load(&buffer).unwrap();
+
+        assert_eq!(
+            probe(&buffer).unwrap(),
+            Probe {
+                format: ImageFormat::PNG,
+                size: size(&image),
+                alpha: false,
+                frames: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn probe_a_webp_image_without_decoding_it() {
+        // Only the frame header of a 320x32 frame, which can not be decoded
+        let buffer =
+            b"RIFF\x16\x00\x00\x00WEBPVP8 \x0a\x00\x00\x00\x10\x02\x00\x9d\x01\x2a\x40\x01\x20\x00";
+
+        assert!(load(buffer).is_err());
+        assert_eq!(
+            probe(buffer).unwrap(),
+            Probe {
+                format: ImageFormat::WEBP,
+                size: PixelSize {
+                    width: 320,
+                    height: 32,
+                },
+                alpha: false,
+                frames: 1,
+            }
+        );
+
+        // An animated 16x16 WebP with alpha, of two frames without frame data
+        let mut buffer = b"RIFF\x34\x00\x00\x00WEBP".to_vec();
+        buffer.extend_from_slice(b"VP8X\x0a\x00\x00\x00\x12\x00\x00\x00\x0f\x00\x00\x0f\x00\x00");
+        buffer.extend_from_slice(b"ANIM\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        for _ in 0..2 {
+            buffer.extend_from_slice(b"ANMF\x00\x00\x00\x00");
+        }
+
+        assert_eq!(
+            probe(&buffer).unwrap(),
+            Probe {
+                format: ImageFormat::WEBP,
+                size: PixelSize {
+                    width: 16,
+                    height: 16,
+                },
+                alpha: true,
+                frames: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_animated_webp_images_with_too_many_frames() {
+        // The extended header and frame chunks, without any frame data
+        let mut buffer = b"RIFF\x48\x00\x00\x00WEBP".to_vec();
+        buffer.extend_from_slice(b"VP8X\x0a\x00\x00\x00\x02\x00\x00\x00\x0f\x00\x00\x0f\x00\x00");
+        buffer.extend_from_slice(b"ANIM\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        for _ in 0..3 {
+            buffer.extend_from_slice(b"ANMF\x04\x00\x00\x00abcd");
+        }
+        let limits = Limits {
+            frames: 2,
+            ..Limits::default()
+        };
+
+        let error = check_input(&buffer, &limits).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TooLarge>(),
+            Some(&TooLarge {
+                what: "frames",
+                size: 3,
+                limit: 2,
+            })
+        );
+        assert!(check_input(&buffer, &Limits::default()).is_ok());
+    }
+
+    #[test]
+    fn rejects_huge_webp_images_from_their_headers() {
+        // Only the frame header, with a 16383x16383 frame that is never decoded
+        let buffer =
+            b"RIFF\x16\x00\x00\x00WEBPVP8 \x0a\x00\x00\x00\x10\x02\x00\x9d\x01\x2a\xff\x3f\xff\x3f";
+
+        let error = check_input(buffer, &Limits::default()).unwrap_err();
+        assert!(error.downcast_ref::<TooLarge>().is_some(), "{}", error);
+    }
+
+    #[test]
+    fn process_a_jpg_image() {
+        let buffer =
+            std::fs::read("./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg").unwrap();
+        let image_size = input_size(&buffer).unwrap();
+
         let transform = Transform::new(
             &image_size,
             TransformMode::Fit {
@@ -206,11 +792,766 @@ mod test {
             },
         );
 
-        let output = process(&mut image, &transform, ImageOutputFormat::JPEG(90), None);
+        let mut image = load_for(&buffer, &transform).unwrap();
+
+        assert!(size(&image).width < image_size.width);
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::JPEG(90),
+            &Colors::default(),
+        );
 
         let mut file =
             std::fs::File::create("tests/output/Apollo_17_Image_Of_Earth_From_Space.jpg").unwrap();
         let result = file.write_all(&output.unwrap());
         result.unwrap();
     }
-}
\ No newline at end of file
+
+    fn half_transparent() -> DynamicImage {
+        // Opaque red on the left, fully transparent green on the right
+        DynamicImage::ImageRgba8(image::RgbaImage::from_fn(8, 2, |x, _| {
+            if x < 4 {
+                image::Rgba {
+                    data: [255, 0, 0, 255],
+                }
+            } else {
+                image::Rgba {
+                    data: [0, 255, 0, 0],
+                }
+            }
+        }))
+    }
+
+    #[test]
+    fn fill_background_after_resizing() {
+        let mut image = half_transparent();
+        let transform = Transform::new(&size(&image), TransformMode::FitWidth(4));
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling {
+                filter: Some(Filter::Triangle),
+                linear: false,
+            },
+            ImageOutputFormat::PNG,
+            &Colors::background([0, 0, 255, 255]),
+        )
+        .unwrap();
+        let output = load_from_memory(&output).unwrap().to_rgba();
+
+        let row: Vec<[u8; 4]> = (0..4).map(|x| output.get_pixel(x, 0).data).collect();
+
+        // The edge blends red into the background, never into the hidden green
+        assert_eq!(
+            row,
+            vec![
+                [255, 0, 0, 255],
+                [223, 0, 32, 255],
+                [32, 0, 223, 255],
+                [0, 0, 255, 255]
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_background_around_the_image() {
+        let mut image = half_transparent();
+        let transform = Transform::new(
+            &size(&image),
+            TransformMode::Fit {
+                width: 8,
+                height: 4,
+            },
+        );
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::PNG,
+            &Colors::background([0, 0, 255, 255]),
+        )
+        .unwrap();
+        let output = load_from_memory(&output).unwrap().to_rgba();
+
+        assert_eq!(output.get_pixel(0, 0).data, [0, 0, 255, 255]);
+        assert_eq!(output.get_pixel(0, 1).data, [255, 0, 0, 255]);
+        assert_eq!(output.get_pixel(7, 2).data, [0, 0, 255, 255]);
+        assert_eq!(output.get_pixel(7, 3).data, [0, 0, 255, 255]);
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::PNG,
+            &Colors::default(),
+        )
+        .unwrap();
+        let output = load_from_memory(&output).unwrap().to_rgba();
+
+        assert_eq!(output.get_pixel(0, 0).data, [0, 0, 0, 0]);
+        assert_eq!(output.get_pixel(0, 1).data, [255, 0, 0, 255]);
+        assert_eq!(output.get_pixel(7, 2).data, [0, 0, 0, 0]);
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::PNG,
+            &Colors::background([0, 0, 255, 128]),
+        )
+        .unwrap();
+        let output = load_from_memory(&output).unwrap().to_rgba();
+
+        assert_eq!(output.get_pixel(0, 0).data, [0, 0, 255, 128]);
+        assert_eq!(output.get_pixel(0, 1).data, [255, 0, 0, 255]);
+        assert_eq!(output.get_pixel(7, 2).data, [0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn fill_background_with_the_dominant_color() {
+        let mut image = half_transparent();
+        let transform = Transform::new(
+            &size(&image),
+            TransformMode::Fit {
+                width: 8,
+                height: 4,
+            },
+        );
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::PNG,
+            &Colors {
+                background: Some(Background::Dominant),
+                ..Colors::default()
+            },
+        )
+        .unwrap();
+        let output = load_from_memory(&output).unwrap().to_rgba();
+
+        assert_eq!(output.get_pixel(0, 0).data, [255, 0, 0, 255]);
+        assert_eq!(output.get_pixel(7, 3).data, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn adjust_the_image_but_not_the_background() {
+        let mut image = half_transparent();
+        let transform = Transform::new(
+            &size(&image),
+            TransformMode::Fit {
+                width: 8,
+                height: 4,
+            },
+        );
+        let colors = Colors {
+            adjustments: Adjustments {
+                brightness: 0.6,
+                ..Adjustments::default()
+            },
+            background: Some(Background::Color([0, 0, 255, 255])),
+            ..Colors::default()
+        };
+
+        for linear in [false, true] {
+            let output = process(
+                &mut image,
+                &transform,
+                &Sampling {
+                    filter: None,
+                    linear,
+                },
+                ImageOutputFormat::PNG,
+                &colors,
+            )
+            .unwrap();
+            let output = load_from_memory(&output).unwrap().to_rgba();
+
+            assert_eq!(output.get_pixel(0, 0).data, [0, 0, 255, 255]);
+            assert_eq!(output.get_pixel(0, 1).data, [153, 0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn encode_grey_effects_with_a_single_channel() {
+        let mut image = half_transparent();
+        let transform = Transform::new(&size(&image), TransformMode::FitWidth(4));
+        let grayscale = |background: Option<[u8; 4]>| Colors {
+            background: background.map(Background::Color),
+            effect: Some(Effect::Grayscale),
+            ..Colors::default()
+        };
+
+        let cases = [
+            (ImageOutputFormat::PNG, grayscale(None), ColorType::GrayA(8)),
+            (
+                ImageOutputFormat::PNG,
+                grayscale(Some([50, 50, 50, 255])),
+                ColorType::Gray(8),
+            ),
+            (
+                ImageOutputFormat::JPEG(90),
+                grayscale(None),
+                ColorType::Gray(8),
+            ),
+            (
+                ImageOutputFormat::PNG,
+                grayscale(Some([0, 0, 255, 255])),
+                ColorType::RGBA(8),
+            ),
+            (
+                ImageOutputFormat::PNG,
+                Colors {
+                    effect: Some(Effect::Sepia),
+                    ..Colors::default()
+                },
+                ColorType::RGBA(8),
+            ),
+        ];
+
+        for (output_format, colors, color_type) in cases.iter() {
+            for degradations in [
+                Degradations::default(),
+                Degradations {
+                    encode: true,
+                    ..Degradations::default()
+                },
+            ] {
+                let output = process_degraded(
+                    &mut image,
+                    &transform,
+                    &Sampling::default(),
+                    output_format.clone(),
+                    colors,
+                    degradations,
+                    false,
+                )
+                .unwrap();
+                let output = load_from_memory(&output).unwrap();
+
+                assert_eq!(output.color(), *color_type, "{:?}", colors.background);
+                if output.color() == ColorType::GrayA(8) {
+                    assert_eq!(output.to_luma_alpha().get_pixel(0, 0).data, [54, 255]);
+                    assert_eq!(output.to_luma_alpha().get_pixel(3, 0).data, [0, 0]);
+                }
+            }
+        }
+    }
+
+    /// Composites a 2x1 image over blue: grey 200 at half alpha, where the
+    /// color type has alpha, then a fully transparent pixel.
+    fn fill_blue(buffer: &[u8]) -> Vec<[u8; 4]> {
+        let transform = Transform::new(&input_size(buffer).unwrap(), TransformMode::FitWidth(2));
+        let sampling = Sampling {
+            filter: Some(Filter::Nearest),
+            linear: false,
+        };
+
+        let mut image = load(buffer).unwrap();
+        let whole = process(
+            &mut image,
+            &transform,
+            &sampling,
+            ImageOutputFormat::PNG,
+            &Colors::background([0, 0, 255, 255]),
+        )
+        .unwrap();
+        let streamed = process_buffer(
+            buffer,
+            &transform,
+            &sampling,
+            ImageOutputFormat::PNG,
+            &Colors::background([0, 0, 255, 255]),
+            Degradations::default(),
+            false,
+        )
+        .unwrap();
+        assert!(whole == streamed);
+
+        let output = load_from_memory(&whole).unwrap().to_rgba();
+        output.pixels().map(|pixel| pixel.data).collect()
+    }
+
+    #[test]
+    fn fill_background_for_every_color_type() {
+        let images = [
+            DynamicImage::ImageLuma8(image::GrayImage::from_raw(2, 1, vec![200, 0]).unwrap()),
+            DynamicImage::ImageLumaA8(
+                image::ImageBuffer::from_raw(2, 1, vec![200, 128, 0, 0]).unwrap(),
+            ),
+            DynamicImage::ImageRgb8(
+                image::RgbImage::from_raw(2, 1, vec![200, 200, 200, 0, 0, 0]).unwrap(),
+            ),
+            DynamicImage::ImageRgba8(
+                image::RgbaImage::from_raw(2, 1, vec![200, 200, 200, 128, 0, 0, 0, 0]).unwrap(),
+            ),
+            DynamicImage::ImageBgr8(
+                image::ImageBuffer::from_raw(2, 1, vec![200, 200, 200, 0, 0, 0]).unwrap(),
+            ),
+            DynamicImage::ImageBgra8(
+                image::ImageBuffer::from_raw(2, 1, vec![200, 200, 200, 128, 0, 0, 0, 0]).unwrap(),
+            ),
+        ];
+
+        for image in images.iter() {
+            let has_alpha = matches!(
+                image,
+                DynamicImage::ImageLumaA8(_)
+                    | DynamicImage::ImageRgba8(_)
+                    | DynamicImage::ImageBgra8(_)
+            );
+            let transform = Transform::new(&size(image), TransformMode::FitWidth(2));
+
+            let output = process(
+                &mut image.clone(),
+                &transform,
+                &Sampling {
+                    filter: Some(Filter::Nearest),
+                    linear: false,
+                },
+                ImageOutputFormat::PNG,
+                &Colors::background([0, 0, 255, 255]),
+            )
+            .unwrap();
+            let output = load_from_memory(&output).unwrap().to_rgba();
+
+            // (200 * 128 + 255 * 127) / 255 rounds to 227 for the blue channel
+            let expected = if has_alpha {
+                [[100, 100, 227, 255], [0, 0, 255, 255]]
+            } else {
+                [[200, 200, 200, 255], [0, 0, 0, 255]]
+            };
+            assert_eq!(
+                output.get_pixel(0, 0).data,
+                expected[0],
+                "{:?}",
+                image.color()
+            );
+            assert_eq!(
+                output.get_pixel(1, 0).data,
+                expected[1],
+                "{:?}",
+                image.color()
+            );
+        }
+    }
+
+    /// A 2x1 PNG of `data`, with optional `PLTE` and `tRNS` chunks.
+    fn png(color_type: u8, bit_depth: u8, data: &[u8], chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let ihdr = [0, 0, 0, 2, 0, 0, 0, 1, bit_depth, color_type, 0, 0, 0];
+        let mut scanline = vec![0];
+        scanline.extend_from_slice(data);
+        let idat = deflate::deflate_bytes_zlib(&scanline);
+
+        let mut all_chunks = vec![(&b"IHDR"[..], &ihdr[..])];
+        all_chunks.extend_from_slice(chunks);
+        all_chunks.push((b"IDAT", &idat));
+        all_chunks.push((b"IEND", &[]));
+
+        let mut buffer = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (name, data) in all_chunks {
+            let start = buffer.len() + 4;
+            buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            buffer.extend_from_slice(name);
+            buffer.extend_from_slice(data);
+            let crc = profile::crc32(&buffer[start..]);
+            buffer.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        buffer
+    }
+
+    #[test]
+    fn fill_background_for_every_png_color_type() {
+        let half = [[100, 100, 227, 255], [0, 0, 255, 255]];
+        let opaque = [[200, 200, 200, 255], [0, 0, 0, 255]];
+        let keyed = [[200, 200, 200, 255], [0, 0, 255, 255]];
+        // 0xC800 rounds to 199 rather than being cut off to 200, while the
+        // 0x8000 of the alpha rounds to 128 either way
+        let opaque_16 = [[199, 199, 199, 255], [0, 0, 0, 255]];
+
+        let cases = [
+            ("grey", png(0, 8, &[200, 0], &[]), opaque),
+            ("grey 16", png(0, 16, &[200, 0, 0, 0], &[]), opaque_16),
+            (
+                "grey 4 with tRNS",
+                png(0, 4, &[0xC0], &[(b"tRNS", &[0, 0])]),
+                [[204, 204, 204, 255], [0, 0, 255, 255]],
+            ),
+            ("grey alpha", png(4, 8, &[200, 128, 0, 0], &[]), half),
+            (
+                "grey alpha 16",
+                png(4, 16, &[200, 0, 128, 0, 0, 0, 0, 0], &[]),
+                half,
+            ),
+            ("rgb", png(2, 8, &[200, 200, 200, 0, 0, 0], &[]), opaque),
+            (
+                "rgb 16",
+                png(2, 16, &[200, 0, 200, 0, 200, 0, 0, 0, 0, 0, 0, 0], &[]),
+                opaque_16,
+            ),
+            (
+                "rgb 16 with tRNS",
+                png(
+                    2,
+                    16,
+                    &[200, 0, 200, 0, 200, 0, 0, 0, 0, 0, 0, 0],
+                    &[(b"tRNS", &[0, 0, 0, 0, 0, 0])],
+                ),
+                [[199, 199, 199, 255], [0, 0, 255, 255]],
+            ),
+            (
+                "rgb with tRNS",
+                png(
+                    2,
+                    8,
+                    &[200, 200, 200, 0, 0, 0],
+                    &[(b"tRNS", &[0, 0, 0, 0, 0, 0])],
+                ),
+                keyed,
+            ),
+            (
+                "rgba",
+                png(6, 8, &[200, 200, 200, 128, 0, 0, 0, 0], &[]),
+                half,
+            ),
+            (
+                "rgba 16",
+                png(
+                    6,
+                    16,
+                    &[200, 0, 200, 0, 200, 0, 128, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                    &[],
+                ),
+                half,
+            ),
+            (
+                "indexed",
+                png(
+                    3,
+                    8,
+                    &[0, 1],
+                    &[(b"PLTE", &[200, 200, 200, 0, 0, 0]), (b"tRNS", &[128, 0])],
+                ),
+                half,
+            ),
+            (
+                "indexed 1",
+                png(
+                    3,
+                    1,
+                    &[0b0100_0000],
+                    &[(b"PLTE", &[200, 200, 200, 0, 0, 0]), (b"tRNS", &[128, 0])],
+                ),
+                half,
+            ),
+        ];
+
+        for (name, buffer, expected) in cases.iter() {
+            assert_eq!(fill_blue(buffer), expected.to_vec(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn shrink_a_large_image_in_stages() {
+        // A checkerboard of single pixels should average out to grey
+        let mut image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(1200, 900, |x, y| {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            image::Rgba {
+                data: [value, value, value, 255],
+            }
+        }));
+        let transform = Transform::new(&size(&image), TransformMode::FitWidth(20));
+
+        let output = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::PNG,
+            &Colors::default(),
+        )
+        .unwrap();
+        let output = load_from_memory(&output).unwrap().to_rgba();
+
+        assert_eq!(output.dimensions(), (20, 15));
+        for pixel in output.pixels() {
+            assert!((i32::from(pixel.data[0]) - 128).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn stream_rows_with_the_same_output() {
+        let sampling = Sampling {
+            filter: None,
+            linear: true,
+        };
+
+        for path in [
+            "./tests/input/test_pattern.png",
+            "./tests/input/simple.png",
+            "./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg",
+        ]
+        .iter()
+        {
+            let buffer = std::fs::read(path).unwrap();
+            let image_size = input_size(&buffer).unwrap();
+
+            for (mode, scale) in [
+                (TransformMode::FitWidth(image_size.width / 9), 1.0),
+                (
+                    TransformMode::Fill {
+                        width: 40,
+                        height: 70,
+                    },
+                    3.0,
+                ),
+            ] {
+                let mut transform = Transform::new(&image_size, mode);
+                transform.scale = scale;
+                transform.relative_center_offset.dx = 0.5;
+                transform.flip.vertical = true;
+
+                let mut image = load_for(&buffer, &transform).unwrap();
+                let whole = process(
+                    &mut image,
+                    &transform,
+                    &sampling,
+                    ImageOutputFormat::PNG,
+                    &Colors::background([10, 20, 30, 255]),
+                )
+                .unwrap();
+                let streamed = process_buffer(
+                    &buffer,
+                    &transform,
+                    &sampling,
+                    ImageOutputFormat::PNG,
+                    &Colors::background([10, 20, 30, 255]),
+                    Degradations::default(),
+                    false,
+                )
+                .unwrap();
+
+                assert!(whole == streamed, "{} at scale {}", path, scale);
+            }
+        }
+    }
+
+    #[test]
+    fn degrade_decoding_and_encoding() {
+        let buffer =
+            std::fs::read("./tests/input/Apollo_17_Image_Of_Earth_From_Space.jpeg").unwrap();
+        let image_size = input_size(&buffer).unwrap();
+        let transform = Transform::new(&image_size, TransformMode::FitWidth(300));
+
+        let process_with = |degradations| {
+            let output = process_buffer(
+                &buffer,
+                &transform,
+                &Sampling::default(),
+                ImageOutputFormat::PNG,
+                &Colors::default(),
+                degradations,
+                false,
+            )
+            .unwrap();
+
+            image::load_from_memory(&output).unwrap()
+        };
+
+        let full = process_with(Degradations::default());
+        let degraded = process_with(Degradations {
+            filter: false,
+            encode: true,
+            decode: true,
+        });
+
+        assert_eq!(degraded.dimensions(), full.dimensions());
+        assert_eq!(degraded.width(), 300);
+    }
+
+    #[test]
+    fn process_a_batch_in_order() {
+        let buffer = std::fs::read("./tests/input/test_pattern.png").unwrap();
+        let image_size = input_size(&buffer).unwrap();
+
+        let jobs: Vec<Job> = [40, 90, 140]
+            .iter()
+            .map(|width| Job {
+                transform: Transform::new(&image_size, TransformMode::FitWidth(*width)),
+                sampling: Sampling::default(),
+                output_format: ImageOutputFormat::PNG,
+                colors: Colors::default(),
+                keep_profile: false,
+            })
+            .collect();
+
+        for (job, output) in jobs.iter().zip(process_batch(&buffer, &jobs)) {
+            let single = process_buffer(
+                &buffer,
+                &job.transform,
+                &job.sampling,
+                job.output_format.clone(),
+                &job.colors,
+                Degradations::default(),
+                job.keep_profile,
+            )
+            .unwrap();
+
+            assert!(output.unwrap() == single);
+        }
+    }
+
+    #[test]
+    fn convert_or_keep_the_icc_profile() {
+        let icc_profile = std::fs::read("./tests/input/wide_gamut.icc").unwrap();
+        let mut source = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([50, 150, 50])))
+            .write_to(&mut source, ImageOutputFormat::PNG)
+            .unwrap();
+        let source = profile::embed(source, &ImageOutputFormat::PNG, &icc_profile).unwrap();
+
+        let transform = Transform::new(
+            &PixelSize {
+                width: 8,
+                height: 8,
+            },
+            TransformMode::FitWidth(4),
+        );
+        let process_with = |keep_profile| {
+            process_buffer(
+                &source,
+                &transform,
+                &Sampling::default(),
+                ImageOutputFormat::PNG,
+                &Colors::default(),
+                Degradations::default(),
+                keep_profile,
+            )
+            .unwrap()
+        };
+
+        let converted = process_with(false);
+        assert_eq!(profile::read(&converted, ImageFormat::PNG).unwrap(), None);
+        let pixel = load(&converted).unwrap().to_rgba().get_pixel(1, 1).data;
+        assert!(pixel[0] < 50 && pixel[1] > 150);
+
+        let kept = process_with(true);
+        assert_eq!(
+            profile::read(&kept, ImageFormat::PNG).unwrap(),
+            Some(icc_profile)
+        );
+        let pixel = load(&kept).unwrap().to_rgba().get_pixel(1, 1).data;
+        assert_eq!(pixel, [50, 150, 50, 255]);
+
+        let mut image = load_for(&source, &transform).unwrap();
+        let processed = process(
+            &mut image,
+            &transform,
+            &Sampling::default(),
+            ImageOutputFormat::PNG,
+            &Colors::default(),
+        )
+        .unwrap();
+        assert!(processed == converted);
+    }
+
+    #[test]
+    fn keep_rgb_channels_for_a_kept_profile() {
+        let icc_profile = std::fs::read("./tests/input/wide_gamut.icc").unwrap();
+        let mut source = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([50, 150, 50])))
+            .write_to(&mut source, ImageOutputFormat::PNG)
+            .unwrap();
+        let source = profile::embed(source, &ImageOutputFormat::PNG, &icc_profile).unwrap();
+
+        let transform = Transform::new(&input_size(&source).unwrap(), TransformMode::FitWidth(4));
+        let colors = Colors {
+            effect: Some(Effect::Grayscale),
+            ..Colors::default()
+        };
+
+        for (output_format, format) in [
+            (ImageOutputFormat::PNG, ImageFormat::PNG),
+            (ImageOutputFormat::JPEG(90), ImageFormat::JPEG),
+        ] {
+            let process_with = |keep_profile| {
+                process_buffer(
+                    &source,
+                    &transform,
+                    &Sampling::default(),
+                    output_format.clone(),
+                    &colors,
+                    Degradations::default(),
+                    keep_profile,
+                )
+                .unwrap()
+            };
+
+            let converted = process_with(false);
+            assert_eq!(load(&converted).unwrap().color(), ColorType::Gray(8));
+
+            // The RGB profile of the source does not describe grey pixels
+            let kept = process_with(true);
+            assert!(matches!(
+                load(&kept).unwrap().color(),
+                ColorType::RGB(8) | ColorType::RGBA(8)
+            ));
+            assert_eq!(
+                profile::read(&kept, format).unwrap(),
+                Some(icc_profile.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn process_cmyk_jpg_images() {
+        for path in ["./tests/input/cmyk.jpg", "./tests/input/ycck.jpg"] {
+            let buffer = std::fs::read(path).unwrap();
+            let image_size = input_size(&buffer).unwrap();
+
+            // Upright, and turned around with the left edge on the right
+            for rotation in [Rotation::None, Rotation::Clockwise180] {
+                let mut transform = Transform::new(&image_size, TransformMode::FitWidth(8));
+                transform.rotation = rotation;
+
+                let output = process_buffer(
+                    &buffer,
+                    &transform,
+                    &Sampling::default(),
+                    ImageOutputFormat::PNG,
+                    &Colors::default(),
+                    Degradations::default(),
+                    false,
+                )
+                .unwrap();
+                let image = load(&output).unwrap().to_rgba();
+                let x = if rotation == Rotation::None { 1 } else { 6 };
+
+                assert_eq!(image.dimensions(), (8, 4));
+                let red = image.get_pixel(x, 2).data;
+                assert!(red[0] > 240 && red[1] < 15 && red[2] < 15, "{:?}", red);
+            }
+        }
+    }
+
+    #[test]
+    fn load_cmyk_jpg_images_in_srgb() {
+        for path in [
+            "./tests/input/cmyk.jpg",
+            "./tests/input/cmyk_without_adobe.jpg",
+            "./tests/input/ycck.jpg",
+        ] {
+            let image = load_srgb(&std::fs::read(path).unwrap()).unwrap().to_rgba();
+            let (width, height) = image.dimensions();
+
+            let red = image.get_pixel(width / 8, height / 2).data;
+            assert!(red[0] > 240 && red[1] < 15 && red[2] < 15, "{:?}", red);
+        }
+    }
+}
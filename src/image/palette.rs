@@ -0,0 +1,298 @@
+use super::resample::{self, FloatImage};
+
+use image::{imageops, DynamicImage, RgbaImage};
+
+/// The longest side of the thumbnail that colors are counted on.
+const THUMBNAIL_SIZE: u32 = 64;
+/// Colors in the palette that the dominant color is picked from, as a single
+/// average color would blend distinct areas into a muddy one.
+const DOMINANT_COUNT: usize = 5;
+const ITERATIONS: usize = 10;
+/// Swatches nearer to each other than this, in levels, are merged, as they
+/// are told apart only by noise.
+const MIN_DISTANCE: f32 = 16.0;
+
+/// A color of a palette, and the percentage of the visible pixels near it.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Swatch {
+    pub color: [u8; 3],
+    pub population: f32,
+}
+
+/// Finds up to `count` colors that the visible pixels cluster around, with
+/// the most common first, on a thumbnail of `image`.
+pub fn extract(image: &DynamicImage, count: usize) -> Vec<Swatch> {
+    let pixels: Vec<[f32; 3]> = thumbnail(image)
+        .pixels()
+        .filter(|pixel| pixel.data[3] >= 128)
+        .map(|pixel| {
+            // Undoes the premultiplication of `thumbnail`
+            let alpha = f32::from(pixel.data[3]);
+            [
+                (f32::from(pixel.data[0]) * 255.0 / alpha).min(255.0),
+                (f32::from(pixel.data[1]) * 255.0 / alpha).min(255.0),
+                (f32::from(pixel.data[2]) * 255.0 / alpha).min(255.0),
+            ]
+        })
+        .collect();
+
+    if pixels.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    let (centers, populations) = cluster(&pixels, median_cut(&pixels, count));
+
+    let mut clusters: Vec<([f32; 3], usize)> = centers
+        .into_iter()
+        .zip(populations)
+        .filter(|(_, population)| *population > 0)
+        .collect();
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.1));
+
+    let mut swatches: Vec<Swatch> = Vec::with_capacity(clusters.len());
+    let mut kept: Vec<[f32; 3]> = Vec::with_capacity(clusters.len());
+
+    for (center, population) in clusters {
+        let population = population as f32 * 100.0 / pixels.len() as f32;
+
+        match kept
+            .iter()
+            .position(|color| distance(color, &center) < MIN_DISTANCE.powi(2))
+        {
+            Some(i) => swatches[i].population += population,
+            None => {
+                kept.push(center);
+                swatches.push(Swatch {
+                    color: [
+                        center[0].round() as u8,
+                        center[1].round() as u8,
+                        center[2].round() as u8,
+                    ],
+                    population,
+                });
+            }
+        }
+    }
+    swatches.sort_by(|a, b| b.population.total_cmp(&a.population));
+
+    swatches
+}
+
+/// Shrinks `image` to at most `THUMBNAIL_SIZE` a side, with premultiplied
+/// colors so that transparent pixels do not bleed into visible ones.
+fn thumbnail(image: &DynamicImage) -> RgbaImage {
+    let mut rgba = image.to_rgba();
+    for pixel in rgba.pixels_mut() {
+        let alpha = u16::from(pixel.data[3]);
+        for channel in pixel.data[..3].iter_mut() {
+            *channel = ((u16::from(*channel) * alpha + 127) / 255) as u8;
+        }
+    }
+
+    let (width, height) = rgba.dimensions();
+    let longest = width.max(height);
+    if longest <= THUMBNAIL_SIZE {
+        return rgba;
+    }
+
+    imageops::thumbnail(
+        &rgba,
+        (width * THUMBNAIL_SIZE / longest).max(1),
+        (height * THUMBNAIL_SIZE / longest).max(1),
+    )
+}
+
+/// The dominant color of a premultiplied canvas, which is in linear light if
+/// `linear`, or `None` if nothing on it is visible.
+pub fn dominant(canvas: &FloatImage, linear: bool) -> Option<[u8; 3]> {
+    let factor = canvas
+        .width
+        .max(canvas.height)
+        .div_ceil(THUMBNAIL_SIZE)
+        .max(1);
+    let mut thumbnail = resample::box_reduce(canvas, factor);
+    thumbnail.unpremultiply();
+    if linear {
+        thumbnail.linear_to_srgb();
+    }
+
+    extract(
+        &DynamicImage::ImageRgba8(thumbnail.to_rgba()),
+        DOMINANT_COUNT,
+    )
+    .first()
+    .map(|swatch| swatch.color)
+}
+
+/// Starting colors for `cluster`: the averages of boxes of the color space
+/// that are split at the median of their widest channel, largest box first.
+fn median_cut(pixels: &[[f32; 3]], count: usize) -> Vec<[f32; 3]> {
+    let mut boxes = vec![pixels.to_vec()];
+
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| (i, widest_channel(colors)))
+            .filter(|(_, (_, range))| *range > 0.0)
+            .max_by(|(a, (_, a_range)), (b, (_, b_range))| {
+                let a_size = a_range * boxes[*a].len() as f32;
+                let b_size = b_range * boxes[*b].len() as f32;
+                a_size.total_cmp(&b_size)
+            });
+
+        let (i, channel) = match widest {
+            Some((i, (channel, _))) => (i, channel),
+            None => break,
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_by(|a, b| a[channel].total_cmp(&b[channel]));
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| mean(colors)).collect()
+}
+
+/// The channel with the largest range of values, and the range.
+fn widest_channel(colors: &[[f32; 3]]) -> (usize, f32) {
+    (0..3)
+        .map(|channel| {
+            let values = colors.iter().map(|color| color[channel]);
+            let min = values.clone().fold(f32::MAX, f32::min);
+            let max = values.fold(f32::MIN, f32::max);
+            (channel, max - min)
+        })
+        .fold((0, 0.0), |widest, channel| {
+            if channel.1 > widest.1 {
+                channel
+            } else {
+                widest
+            }
+        })
+}
+
+fn mean(colors: &[[f32; 3]]) -> [f32; 3] {
+    let mut sum = [0.0; 3];
+
+    for color in colors {
+        for (total, value) in sum.iter_mut().zip(color.iter()) {
+            *total += value;
+        }
+    }
+
+    let count = colors.len().max(1) as f32;
+    [sum[0] / count, sum[1] / count, sum[2] / count]
+}
+
+/// Moves each center to the mean of the pixels nearest to it until they
+/// settle, returning the centers and their pixel counts.
+fn cluster(pixels: &[[f32; 3]], mut centers: Vec<[f32; 3]>) -> (Vec<[f32; 3]>, Vec<usize>) {
+    let mut nearest = vec![0; pixels.len()];
+
+    for iteration in 0..ITERATIONS {
+        let mut changed = false;
+
+        for (pixel, nearest) in pixels.iter().zip(nearest.iter_mut()) {
+            let (i, _) = centers
+                .iter()
+                .map(|center| distance(pixel, center))
+                .enumerate()
+                .fold((0, f32::MAX), |closest, (i, distance)| {
+                    if distance < closest.1 {
+                        (i, distance)
+                    } else {
+                        closest
+                    }
+                });
+
+            changed |= *nearest != i;
+            *nearest = i;
+        }
+
+        if !changed && iteration > 0 {
+            break;
+        }
+
+        for (i, center) in centers.iter_mut().enumerate() {
+            let members: Vec<[f32; 3]> = pixels
+                .iter()
+                .zip(nearest.iter())
+                .filter(|(_, nearest)| **nearest == i)
+                .map(|(pixel, _)| *pixel)
+                .collect();
+
+            if !members.is_empty() {
+                *center = mean(&members);
+            }
+        }
+    }
+
+    let mut populations = vec![0; centers.len()];
+    for i in nearest {
+        populations[i] += 1;
+    }
+
+    (centers, populations)
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dominant, extract, Swatch};
+    use crate::image::resample::FloatImage;
+    use image::DynamicImage;
+
+    /// Three quarters red and a quarter blue, with a transparent green row.
+    fn red_and_blue() -> DynamicImage {
+        DynamicImage::ImageRgba8(image::RgbaImage::from_fn(200, 101, |x, y| image::Rgba {
+            data: if y == 100 {
+                [0, 255, 0, 0]
+            } else if x < 150 {
+                [200, 10, 10, 255]
+            } else {
+                [10, 10, 200, 255]
+            },
+        }))
+    }
+
+    #[test]
+    fn extracts_a_palette_with_populations() {
+        let palette = extract(&red_and_blue(), 4);
+
+        assert_eq!(palette.len(), 2, "{:?}", palette);
+        assert_eq!(palette[0].color, [200, 10, 10]);
+        assert_eq!(palette[1].color, [10, 10, 200]);
+        assert!((palette[0].population - 75.0).abs() < 2.0, "{:?}", palette);
+        assert!((palette[0].population + palette[1].population - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn extracts_nothing_from_transparent_images() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(10, 10));
+
+        assert_eq!(extract(&image, 4), Vec::<Swatch>::new());
+    }
+
+    #[test]
+    fn finds_the_dominant_color_of_a_canvas() {
+        for linear in [false, true] {
+            let rgba = red_and_blue().to_rgba();
+            let mut canvas = if linear {
+                FloatImage::from_rgba_linear(&rgba)
+            } else {
+                FloatImage::from_rgba(&rgba)
+            };
+            canvas.premultiply();
+
+            assert_eq!(dominant(&canvas, linear), Some([200, 10, 10]));
+        }
+
+        assert_eq!(dominant(&FloatImage::new(4, 4), false), None);
+    }
+}
@@ -0,0 +1,48 @@
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(feature = "parallel")] {
+        use rayon::prelude::*;
+
+        /// Calls `f` with the index and pixels of each row of `width` pixels,
+        /// on a thread pool. Rows must not depend on each other.
+        pub fn for_each_row<T, F>(pixels: &mut [T], width: usize, f: F)
+        where
+            T: Send,
+            F: Fn(usize, &mut [T]) + Sync + Send,
+        {
+            pixels
+                .par_chunks_mut(width.max(1))
+                .enumerate()
+                .for_each(|(y, row)| f(y, row));
+        }
+
+        /// Maps `items` in order, on a thread pool.
+        pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+        where
+            T: Sync,
+            U: Send,
+            F: Fn(&T) -> U + Sync + Send,
+        {
+            items.par_iter().map(f).collect()
+        }
+    } else {
+        /// Calls `f` with the index and pixels of each row of `width` pixels.
+        pub fn for_each_row<T, F>(pixels: &mut [T], width: usize, f: F)
+        where
+            F: Fn(usize, &mut [T]),
+        {
+            for (y, row) in pixels.chunks_mut(width.max(1)).enumerate() {
+                f(y, row);
+            }
+        }
+
+        /// Maps `items` in order.
+        pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+        where
+            F: Fn(&T) -> U,
+        {
+            items.iter().map(f).collect()
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use super::decode::skip_chunk;
+use super::stream::Rows;
+use super::transform::PixelSize;
+
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use qcms::{DataType, Intent, Profile, Transform};
+
+const ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+/// The most profile data that fits in one JPEG APP2 segment.
+const JPEG_CHUNK: usize = 65535 - 2 - 14;
+
+/// Reads the ICC profile embedded in JPEG, PNG and WebP images.
+pub fn read(buffer: &[u8], format: ImageFormat) -> Result<Option<Vec<u8>>, failure::Error> {
+    match format {
+        ImageFormat::JPEG => {
+            let mut decoder = jpeg_decoder::Decoder::new(buffer);
+            decoder.read_info()?;
+
+            Ok(decoder.icc_profile())
+        }
+        ImageFormat::PNG => png_profile(buffer),
+        ImageFormat::WEBP => Ok(webp_profile(buffer)),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the compressed profile from the `iCCP` chunk, which comes before the
+/// image data.
+fn png_profile(buffer: &[u8]) -> Result<Option<Vec<u8>>, failure::Error> {
+    let mut position = 8;
+
+    while let Some(header) = buffer.get(position..position + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+        match &header[4..8] {
+            b"iCCP" => {
+                let data = &buffer[position + 8..skip_chunk(buffer, position + 8, length)?];
+                // Profile name, its null separator and the compression method
+                let start = data
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .ok_or_else(|| failure::format_err!("invalid iCCP chunk"))?
+                    + 2;
+                let profile = inflate::inflate_bytes_zlib(data.get(start..).unwrap_or(&[]))
+                    .map_err(|e| failure::format_err!("invalid iCCP chunk {}", e))?;
+
+                return Ok(Some(profile));
+            }
+            b"IDAT" => break,
+            _ => position = skip_chunk(buffer, position + 12, length)?,
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the `ICCP` chunk of extended WebP files.
+fn webp_profile(buffer: &[u8]) -> Option<Vec<u8>> {
+    // RIFF header, file size and WEBP
+    let mut position = 12;
+
+    while let Some(header) = buffer.get(position..position + 8) {
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+
+        let end = skip_chunk(buffer, position + 8, length).ok()?;
+
+        if &header[0..4] == b"ICCP" {
+            return Some(buffer[position + 8..end].to_vec());
+        }
+
+        // Chunks are padded to an even length
+        position = end + length % 2;
+    }
+
+    None
+}
+
+/// A transform of RGBA pixels from `profile` to sRGB, or `None` when the
+/// profile is sRGB already, is not an RGB profile or can not be used.
+pub fn to_srgb(profile: &[u8]) -> Option<Transform> {
+    // qcms expects pixels that match the color space of the profile
+    if !is_rgb(profile) {
+        return None;
+    }
+
+    let profile = Profile::new_from_slice(profile, false)?;
+    if profile.is_sRGB() {
+        return None;
+    }
+
+    Transform::new(&profile, &srgb(), DataType::RGBA8, Intent::default())
+}
+
+/// A transform from CMYK pixels, with 0 for no ink, to RGB pixels in sRGB.
+pub fn cmyk_to_srgb(profile: &[u8]) -> Option<Transform> {
+    // qcms converts CMYK only through the lookup table of the A2B0 tag
+    if profile.get(16..20) != Some(b"CMYK") || !has_tag(profile, b"A2B0") {
+        return None;
+    }
+
+    let profile = Profile::new_from_slice(profile, false)?;
+
+    Transform::new_to(
+        &profile,
+        &srgb(),
+        DataType::CMYK,
+        DataType::RGB8,
+        Intent::default(),
+    )
+}
+
+/// Whether the profile describes RGB pixels, like the ones that are encoded.
+pub fn is_rgb(profile: &[u8]) -> bool {
+    profile.get(16..20) == Some(b"RGB ")
+}
+
+fn has_tag(profile: &[u8], signature: &[u8]) -> bool {
+    let count = match profile.get(128..132) {
+        Some(count) => u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize,
+        None => return false,
+    };
+
+    (0..count.min(profile.len() / 12))
+        .any(|i| profile.get(132 + i * 12..136 + i * 12) == Some(signature))
+}
+
+fn srgb() -> Box<Profile> {
+    let mut srgb = Profile::new_sRGB();
+    srgb.precache_output_transform();
+
+    srgb
+}
+
+pub fn convert(image: DynamicImage, transform: &Transform) -> DynamicImage {
+    let mut image = image.to_rgba();
+    transform.apply(&mut image);
+
+    DynamicImage::ImageRgba8(image)
+}
+
+/// Converts rows to sRGB as they are read.
+pub struct SrgbRows<'a> {
+    rows: Box<dyn Rows + 'a>,
+    transform: Transform,
+}
+
+impl<'a> SrgbRows<'a> {
+    pub fn new(rows: Box<dyn Rows + 'a>, transform: Transform) -> Self {
+        SrgbRows { rows, transform }
+    }
+}
+
+impl<'a> Rows for SrgbRows<'a> {
+    fn size(&self) -> PixelSize {
+        self.rows.size()
+    }
+
+    fn read_row(&mut self, row: &mut [[u8; 4]]) -> Result<(), failure::Error> {
+        self.rows.read_row(row)?;
+        self.transform.apply(row.as_flattened_mut());
+
+        Ok(())
+    }
+}
+
+/// Embeds `profile` in an encoded PNG or JPEG.
+pub fn embed(
+    output: Vec<u8>,
+    output_format: &ImageOutputFormat,
+    profile: &[u8],
+) -> Result<Vec<u8>, failure::Error> {
+    match output_format {
+        ImageOutputFormat::PNG => {
+            let mut data = b"icc\0\0".to_vec();
+            data.extend_from_slice(&deflate::deflate_bytes_zlib(profile));
+
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(b"iCCP");
+            chunk.extend_from_slice(&data);
+            chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+
+            // Right after the signature and IHDR chunk
+            Ok(splice(output, 8 + 25, &chunk))
+        }
+        ImageOutputFormat::JPEG(_) => {
+            let count = profile.len().div_ceil(JPEG_CHUNK);
+            if count > 255 {
+                return Err(failure::format_err!("profile too large to embed"));
+            }
+
+            let mut segments = Vec::with_capacity(profile.len() + count * 18);
+            for (i, chunk) in profile.chunks(JPEG_CHUNK).enumerate() {
+                segments.extend_from_slice(&[0xFF, 0xE2]);
+                segments.extend_from_slice(&((2 + 14 + chunk.len()) as u16).to_be_bytes());
+                segments.extend_from_slice(ICC_MARKER);
+                segments.extend_from_slice(&[i as u8 + 1, count as u8]);
+                segments.extend_from_slice(chunk);
+            }
+
+            // After SOI and the JFIF APP0 segment, which must come first
+            let position = match output.get(2..6) {
+                Some([0xFF, 0xE0, high, low]) => 4 + usize::from(u16::from_be_bytes([*high, *low])),
+                _ => 2,
+            };
+
+            Ok(splice(output, position, &segments))
+        }
+        _ => Err(failure::format_err!("unsupported output format")),
+    }
+}
+
+fn splice(output: Vec<u8>, position: usize, data: &[u8]) -> Vec<u8> {
+    let mut spliced = Vec::with_capacity(output.len() + data.len());
+    spliced.extend_from_slice(&output[..position]);
+    spliced.extend_from_slice(data);
+    spliced.extend_from_slice(&output[position..]);
+
+    spliced
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::{embed, read, to_srgb};
+    use image::{ImageFormat, ImageOutputFormat};
+
+    #[test]
+    fn embeds_and_reads_profiles() {
+        let profile = std::fs::read("./tests/input/wide_gamut.icc").unwrap();
+        let image =
+            image::load_from_memory(&std::fs::read("./tests/input/simple.png").unwrap()).unwrap();
+
+        for (output_format, format) in [
+            (ImageOutputFormat::PNG, ImageFormat::PNG),
+            (ImageOutputFormat::JPEG(90), ImageFormat::JPEG),
+        ] {
+            let mut output = Vec::new();
+            image.write_to(&mut output, output_format.clone()).unwrap();
+            assert_eq!(read(&output, format).unwrap(), None);
+
+            let output = embed(output, &output_format, &profile).unwrap();
+
+            assert_eq!(read(&output, format).unwrap(), Some(profile.clone()));
+            assert!(image::load_from_memory(&output).is_ok());
+        }
+    }
+
+    #[test]
+    fn stops_at_forged_chunk_lengths() {
+        let png = std::fs::read("./tests/input/simple.png").unwrap();
+        let mut buffer = png[..33].to_vec();
+        buffer.extend_from_slice(b"\xff\xff\xff\xf8tEXt");
+        buffer.extend_from_slice(&[0; 16]);
+
+        assert!(read(&buffer, ImageFormat::PNG).is_err());
+
+        let buffer = b"RIFF\x1c\x00\x00\x00WEBPEXIF\xff\xff\xff\xffICCP\x04\x00\x00\x00abcd";
+
+        assert_eq!(read(buffer, ImageFormat::WEBP).unwrap(), None);
+
+        // Profile chunks whose length would wrap the end around as well
+        let mut buffer = png[..33].to_vec();
+        buffer.extend_from_slice(b"\xff\xff\xff\xf8iCCP");
+        buffer.extend_from_slice(b"icc\0\0");
+        buffer.extend_from_slice(&[0; 16]);
+
+        assert!(read(&buffer, ImageFormat::PNG).is_err());
+
+        let buffer = b"RIFF\x14\x00\x00\x00WEBPICCP\xf8\xff\xff\xffabcd";
+
+        assert_eq!(read(buffer, ImageFormat::WEBP).unwrap(), None);
+    }
+
+    #[test]
+    fn converts_wide_gamut_pixels_to_srgb() {
+        let profile = std::fs::read("./tests/input/wide_gamut.icc").unwrap();
+        let transform = to_srgb(&profile).unwrap();
+
+        let mut pixels = [50, 150, 50, 255, 128, 128, 128, 128];
+        transform.apply(&mut pixels);
+
+        // Green is more saturated in the wider gamut, grey stays grey
+        assert!(pixels[0] < 50 && pixels[1] > 150 && pixels[2] < 50);
+        assert_eq!(pixels[4], pixels[5]);
+        assert_eq!(pixels[5], pixels[6]);
+        assert_eq!(pixels[7], 128);
+    }
+}
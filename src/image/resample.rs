@@ -0,0 +1,1182 @@
+use super::affine::Affine;
+use super::kernels::{accumulate, accumulate_row, accumulate_weighted, scale};
+use super::parallel;
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use image::{Rgba, RgbaImage};
+
+/// RGBA pixels with each channel normalised to `0.0..=1.0`. Alpha is straight
+/// unless `premultiply` has been called.
+#[derive(Clone)]
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl FloatImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        FloatImage {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; (width as usize) * (height as usize)],
+        }
+    }
+
+    pub fn from_rgba(image: &RgbaImage) -> Self {
+        Self::from_rgba_with(image, &channel_table(false))
+    }
+
+    /// Like `from_rgba`, but with the colour channels converted from sRGB to
+    /// linear light so that resampling does not darken fine detail.
+    pub fn from_rgba_linear(image: &RgbaImage) -> Self {
+        Self::from_rgba_with(image, &channel_table(true))
+    }
+
+    fn from_rgba_with(image: &RgbaImage, table: &[f32]) -> Self {
+        FloatImage {
+            width: image.width(),
+            height: image.height(),
+            pixels: image
+                .pixels()
+                .map(|pixel| to_float(table, pixel.data))
+                .collect(),
+        }
+    }
+
+    pub fn linear_to_srgb(&mut self) {
+        parallel::for_each_row(&mut self.pixels, self.width as usize, |_, row| {
+            for pixel in row.iter_mut() {
+                pixel[0] = linear_to_srgb(pixel[0]);
+                pixel[1] = linear_to_srgb(pixel[1]);
+                pixel[2] = linear_to_srgb(pixel[2]);
+            }
+        });
+    }
+
+    /// Weighs colour by alpha, so that resampling does not bleed the colour of
+    /// transparent pixels into their neighbours.
+    pub fn premultiply(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            premultiply(pixel);
+        }
+    }
+
+    pub fn unpremultiply(&mut self) {
+        parallel::for_each_row(&mut self.pixels, self.width as usize, |_, row| {
+            for pixel in row.iter_mut() {
+                let alpha = pixel[3].clamp(0.0, 1.0);
+
+                if alpha > 0.0 {
+                    pixel[0] = (pixel[0] / alpha).clamp(0.0, 1.0);
+                    pixel[1] = (pixel[1] / alpha).clamp(0.0, 1.0);
+                    pixel[2] = (pixel[2] / alpha).clamp(0.0, 1.0);
+                } else {
+                    *pixel = [0.0; 4];
+                }
+
+                pixel[3] = alpha;
+            }
+        });
+    }
+
+    /// Composites premultiplied pixels over `color`, which has straight alpha.
+    pub fn fill_premultiplied(&mut self, color: [f32; 4]) {
+        let alpha = color[3].clamp(0.0, 1.0);
+        let premultiplied = [color[0] * alpha, color[1] * alpha, color[2] * alpha, 0.0];
+
+        parallel::for_each_row(&mut self.pixels, self.width as usize, |_, row| {
+            for pixel in row.iter_mut() {
+                let transparency = 1.0 - pixel[3].clamp(0.0, 1.0);
+
+                accumulate(pixel, &premultiplied, transparency);
+                // Exactly opaque over an opaque color
+                pixel[3] = 1.0 - (1.0 - alpha) * transparency;
+            }
+        });
+    }
+
+    pub fn to_rgba(&self) -> RgbaImage {
+        let mut image = RgbaImage::new(self.width, self.height);
+
+        for (pixel_mut, pixel) in image.pixels_mut().zip(self.pixels.iter()) {
+            *pixel_mut = Rgba {
+                data: [
+                    to_u8(pixel[0]),
+                    to_u8(pixel[1]),
+                    to_u8(pixel[2]),
+                    to_u8(pixel[3]),
+                ],
+            };
+        }
+
+        image
+    }
+}
+
+/// Values of the 8-bit channels `0..=255` in `FloatImage`, in linear light if
+/// `linear`.
+pub fn channel_table(linear: bool) -> Vec<f32> {
+    (0..256)
+        .map(|value| {
+            if linear {
+                srgb_to_linear(value as f32 / 255.0)
+            } else {
+                value as f32 / 255.0
+            }
+        })
+        .collect()
+}
+
+/// Converts one pixel using a `channel_table`, leaving alpha straight.
+pub fn to_float(table: &[f32], pixel: [u8; 4]) -> [f32; 4] {
+    [
+        table[pixel[0] as usize],
+        table[pixel[1] as usize],
+        table[pixel[2] as usize],
+        f32::from(pixel[3]) / 255.0,
+    ]
+}
+
+pub fn premultiply(pixel: &mut [f32; 4]) {
+    pixel[0] *= pixel[3];
+    pixel[1] *= pixel[3];
+    pixel[2] *= pixel[3];
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl Filter {
+    /// Lanczos keeps downscales sharp, while Catmull-Rom rings less when
+    /// enlarging.
+    pub fn for_affine(affine: &Affine) -> Self {
+        let area = (affine.a * affine.d - affine.b * affine.c).abs();
+
+        if area < 1.0 {
+            Filter::Lanczos3
+        } else {
+            Filter::CatmullRom
+        }
+    }
+
+    pub fn support(self) -> f32 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Triangle => 1.0,
+            Filter::CatmullRom => 2.0,
+            Filter::Gaussian | Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        match self {
+            Filter::Nearest => {
+                if x.abs() <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Triangle => (1.0 - x.abs()).max(0.0),
+            Filter::CatmullRom => catmull_rom(x),
+            Filter::Gaussian => (-2.0 * x * x).exp() * (2.0 / std::f32::consts::PI).sqrt(),
+            Filter::Lanczos3 => {
+                if x.abs() < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn catmull_rom(x: f32) -> f32 {
+    let x = x.abs();
+
+    if x < 1.0 {
+        1.5 * x * x * x - 2.5 * x * x + 1.0
+    } else if x < 2.0 {
+        -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// Source pixel weights contributing to one output pixel along one axis.
+struct Weights {
+    start: usize,
+    values: Vec<f32>,
+}
+
+impl Weights {
+    fn end(&self) -> usize {
+        self.start + self.values.len()
+    }
+}
+
+/// `center` is a source coordinate and `footprint` the number of source
+/// pixels covered by one output pixel along the same axis.
+fn axis_weights(center: f32, footprint: f32, length: u32, filter: Filter) -> Weights {
+    let (start, end) = axis_range(center, footprint, length, filter);
+
+    if filter == Filter::Nearest {
+        return Weights {
+            start,
+            values: vec![1.0],
+        };
+    }
+
+    let center = center.clamp(0.0, length as f32);
+    let scale = footprint.max(1.0);
+
+    let mut values: Vec<f32> = (start..end)
+        .map(|i| filter.kernel((i as f32 + 0.5 - center) / scale))
+        .collect();
+
+    let sum: f32 = values.iter().sum();
+    if sum > 0.0 {
+        for value in values.iter_mut() {
+            *value /= sum;
+        }
+    }
+
+    Weights { start, values }
+}
+
+/// The source pixels `start..end` read by `axis_weights`, which only ever
+/// move forward as `center` does.
+fn axis_range(center: f32, footprint: f32, length: u32, filter: Filter) -> (usize, usize) {
+    let center = center.clamp(0.0, length as f32);
+
+    if filter == Filter::Nearest {
+        let start = (center.floor() as usize).min(length as usize - 1);
+        return (start, start + 1);
+    }
+
+    let support = filter.support() * footprint.max(1.0);
+
+    let start = (center - support).floor().max(0.0) as usize;
+    let end = ((center + support).ceil() as usize).min(length as usize);
+
+    (start, end)
+}
+
+/// How much of an output pixel centered at the source coordinate `center` is
+/// covered by the source, anti-aliasing the edges that land between pixels.
+fn coverage(center: f32, footprint: f32, length: u32) -> f32 {
+    let inside = center.min(length as f32 - center);
+
+    (0.5 + inside / footprint).clamp(0.0, 1.0)
+}
+
+/// How much a source is reduced by the filter itself after `reduction_factor`.
+const FINAL_REDUCTION: f32 = 2.0;
+
+/// An integer factor to `box_reduce` a `width` x `height` source by before
+/// resampling, which is cheaper and aliases less than filtering very large
+/// reductions in one pass. Returns 1 when no box reduction is needed.
+pub fn reduction_factor(affine: &Affine, filter: Filter, width: u32, height: u32) -> u32 {
+    if filter == Filter::Nearest {
+        return 1;
+    }
+
+    let inverse = match affine.invert() {
+        Some(inverse) => inverse,
+        None => return 1,
+    };
+    let footprint = inverse.a.hypot(inverse.c).min(inverse.b.hypot(inverse.d));
+
+    ((footprint / FINAL_REDUCTION).floor() as u32).clamp(1, width.max(height).max(1))
+}
+
+/// Averages each `factor` x `factor` block of pixels into one, with partial
+/// blocks along the right and bottom edges averaging the pixels they have.
+pub fn box_reduce(image: &FloatImage, factor: u32) -> FloatImage {
+    let source_width = image.width as usize;
+    let mut output = FloatImage::new(image.width.div_ceil(factor), image.height.div_ceil(factor));
+    let width = output.width as usize;
+
+    for (output_row, source_rows) in output
+        .pixels
+        .chunks_mut(width)
+        .zip(image.pixels.chunks(source_width * factor as usize))
+    {
+        for source_row in source_rows.chunks(source_width) {
+            box_accumulate(output_row, source_row, factor);
+        }
+        box_average(
+            output_row,
+            source_width,
+            source_rows.len() / source_width,
+            factor,
+        );
+    }
+
+    output
+}
+
+/// Adds a row of `source` pixels into the blocks of `output`, for `box_reduce`.
+pub fn box_accumulate(output: &mut [[f32; 4]], source: &[[f32; 4]], factor: u32) {
+    let factor = factor as usize;
+
+    for (x, pixel) in source.iter().enumerate() {
+        accumulate(&mut output[x / factor], pixel, 1.0);
+    }
+}
+
+/// Turns the sums of `box_accumulate` over `rows` rows of `source_width`
+/// pixels into averages.
+pub fn box_average(output: &mut [[f32; 4]], source_width: usize, rows: usize, factor: u32) {
+    let factor = factor as usize;
+
+    for (x, pixel) in output.iter_mut().enumerate() {
+        let columns = ((x + 1) * factor).min(source_width) - x * factor;
+        scale(pixel, 1.0 / (rows * columns) as f32);
+    }
+}
+
+/// How a source is box reduced before resampling, and which part of it the
+/// canvas shows.
+pub struct Reduction {
+    pub factor: u32,
+    /// Size of the whole source after reducing it.
+    pub width: u32,
+    pub height: u32,
+    /// Maps the reduced source onto the canvas.
+    pub affine: Affine,
+    /// The reduced pixels that resampling reads.
+    pub bounds: Bounds,
+    source_width: u32,
+    source_height: u32,
+}
+
+impl Reduction {
+    pub fn new(
+        source_width: u32,
+        source_height: u32,
+        affine: &Affine,
+        width: u32,
+        height: u32,
+        filter: Filter,
+    ) -> Self {
+        let factor = reduction_factor(affine, filter, source_width, source_height);
+        let reduced_width = source_width.div_ceil(factor);
+        let reduced_height = source_height.div_ceil(factor);
+
+        let affine = Affine::scale(
+            source_width as f32 / reduced_width as f32,
+            source_height as f32 / reduced_height as f32,
+        )
+        .then(affine);
+
+        let bounds = visible_bounds(
+            reduced_width,
+            reduced_height,
+            &affine,
+            width,
+            height,
+            filter,
+        )
+        .unwrap_or_default();
+
+        Reduction {
+            factor,
+            width: reduced_width,
+            height: reduced_height,
+            affine,
+            bounds,
+            source_width,
+            source_height,
+        }
+    }
+
+    /// The source pixels that are averaged into `bounds`.
+    pub fn source_bounds(&self) -> Bounds {
+        let left = self.bounds.left * self.factor;
+        let top = self.bounds.top * self.factor;
+        let right = ((self.bounds.left + self.bounds.width) * self.factor).min(self.source_width);
+        let bottom = ((self.bounds.top + self.bounds.height) * self.factor).min(self.source_height);
+
+        Bounds {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+}
+
+/// A rectangle of source pixels, in the coordinates of the whole source.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Bounds {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Part of a source image of `source_width` x `source_height`, holding only
+/// the pixels inside `bounds`.
+pub struct Window<'a> {
+    pub image: &'a FloatImage,
+    pub bounds: Bounds,
+    pub source_width: u32,
+    pub source_height: u32,
+}
+
+impl<'a> Window<'a> {
+    pub fn whole(image: &'a FloatImage) -> Self {
+        Window {
+            image,
+            bounds: Bounds {
+                left: 0,
+                top: 0,
+                width: image.width,
+                height: image.height,
+            },
+            source_width: image.width,
+            source_height: image.height,
+        }
+    }
+
+    /// Pixels `start..start + length` of source row `y`.
+    fn row(&self, y: usize, start: usize, length: usize) -> &[[f32; 4]] {
+        let row_start = (y - self.bounds.top as usize) * (self.bounds.width as usize)
+            + (start - self.bounds.left as usize);
+
+        &self.image.pixels[row_start..row_start + length]
+    }
+}
+
+/// The source pixels that `resample` reads when rendering onto a `width` x
+/// `height` canvas, or `None` when it reads none at all. Sampling positions
+/// move steadily across the canvas, so the extremes are found at its corners.
+pub fn visible_bounds(
+    source_width: u32,
+    source_height: u32,
+    affine: &Affine,
+    width: u32,
+    height: u32,
+    filter: Filter,
+) -> Option<Bounds> {
+    if source_width == 0 || source_height == 0 || width == 0 || height == 0 {
+        return None;
+    }
+
+    let inverse = affine.invert()?;
+    let footprint_x = inverse.a.hypot(inverse.c);
+    let footprint_y = inverse.b.hypot(inverse.d);
+
+    let right = (width - 1) as f32 + 0.5;
+    let bottom = (height - 1) as f32 + 0.5;
+    let corners = [
+        inverse.apply(0.5, 0.5),
+        inverse.apply(right, 0.5),
+        inverse.apply(0.5, bottom),
+        inverse.apply(right, bottom),
+    ];
+
+    let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+    let max_x = corners
+        .iter()
+        .map(|c| c.0)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+    let max_y = corners
+        .iter()
+        .map(|c| c.1)
+        .fold(f32::NEG_INFINITY, f32::max);
+
+    let (left, _) = axis_range(min_x, footprint_x, source_width, filter);
+    let (_, right) = axis_range(max_x, footprint_x, source_width, filter);
+    let (top, _) = axis_range(min_y, footprint_y, source_height, filter);
+    let (_, bottom) = axis_range(max_y, footprint_y, source_height, filter);
+
+    Some(Bounds {
+        left: left as u32,
+        top: top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
+
+/// Renders premultiplied `source` onto a transparent `width` x `height` canvas, where
+/// `affine` maps source coordinates to canvas coordinates.
+pub fn resample(
+    source: &FloatImage,
+    affine: &Affine,
+    width: u32,
+    height: u32,
+    filter: Filter,
+) -> FloatImage {
+    resample_window(&Window::whole(source), affine, width, height, filter)
+}
+
+/// Like `resample`, but reading from a window that must cover the
+/// `visible_bounds` of the source.
+pub fn resample_window(
+    source: &Window,
+    affine: &Affine,
+    width: u32,
+    height: u32,
+    filter: Filter,
+) -> FloatImage {
+    let inverse = match affine.invert() {
+        Some(inverse) => inverse,
+        None => return FloatImage::new(width, height),
+    };
+
+    if source.bounds.width == 0 || source.bounds.height == 0 {
+        FloatImage::new(width, height)
+    } else if inverse.is_axis_aligned() {
+        resample_separable(source, &inverse, width, height, filter)
+    } else {
+        resample_general(source, &inverse, width, height, filter)
+    }
+}
+
+fn resample_separable(
+    source: &Window,
+    inverse: &Affine,
+    width: u32,
+    height: u32,
+    filter: Filter,
+) -> FloatImage {
+    let resampler = RowResampler::new(
+        inverse,
+        source.bounds.left,
+        source.source_width,
+        source.source_height,
+        width,
+        height,
+        filter,
+    );
+
+    // With the whole source at hand, every row of each pass can be resampled
+    // independently of the others
+    let first = resampler.first;
+    let source_rows: Vec<usize> = resampler.rows().collect();
+    let horizontal = parallel::map(&source_rows, |y| {
+        horizontal_pass(
+            &resampler.columns,
+            resampler.left,
+            source.row(
+                *y,
+                source.bounds.left as usize,
+                source.bounds.width as usize,
+            ),
+        )
+    });
+
+    let mut output = FloatImage::new(width, height);
+    parallel::for_each_row(&mut output.pixels, width as usize, |y, output_row| {
+        if resampler.rows[y].0 > 0.0 {
+            vertical_pass(&resampler.rows[y], &resampler.columns, output_row, |y| {
+                &horizontal[y - first]
+            });
+        }
+    });
+
+    output
+}
+
+/// Resamples one axis at a time from source rows that are pushed from the top
+/// down, keeping only the rows that unfinished canvas rows still need.
+pub struct RowResampler {
+    left: usize,
+    columns: Vec<(f32, Weights)>,
+    rows: Vec<(f32, Weights)>,
+    /// Covered canvas rows, in the order they can be finished.
+    pending: Vec<usize>,
+    /// The first source row that `pending[i..]` still needs.
+    needed: Vec<usize>,
+    finished: usize,
+    /// Source rows after the horizontal pass, starting from row `first`.
+    horizontal: VecDeque<Vec<[f32; 4]>>,
+    first: usize,
+    end: usize,
+    output: FloatImage,
+}
+
+impl RowResampler {
+    /// `inverse` maps canvas coordinates onto a source of `source_width` x
+    /// `source_height`, and must be axis aligned. Pushed rows start at
+    /// column `left` of the source.
+    pub fn new(
+        inverse: &Affine,
+        left: u32,
+        source_width: u32,
+        source_height: u32,
+        width: u32,
+        height: u32,
+        filter: Filter,
+    ) -> Self {
+        let footprint_x = inverse.a.abs();
+        let footprint_y = inverse.d.abs();
+
+        let columns: Vec<(f32, Weights)> = (0..width)
+            .map(|x| {
+                let center = inverse.a * (x as f32 + 0.5) + inverse.e;
+                (
+                    coverage(center, footprint_x, source_width),
+                    axis_weights(center, footprint_x, source_width, filter),
+                )
+            })
+            .collect();
+
+        let rows: Vec<(f32, Weights)> = (0..height)
+            .map(|y| {
+                let center = inverse.d * (y as f32 + 0.5) + inverse.f;
+                (
+                    coverage(center, footprint_y, source_height),
+                    axis_weights(center, footprint_y, source_height, filter),
+                )
+            })
+            .collect();
+
+        let mut pending: Vec<usize> = (0..rows.len()).filter(|y| rows[*y].0 > 0.0).collect();
+        pending.sort_by_key(|y| rows[*y].1.end());
+
+        let mut needed = vec![usize::MAX; pending.len() + 1];
+        for (i, y) in pending.iter().enumerate().rev() {
+            needed[i] = needed[i + 1].min(rows[*y].1.start);
+        }
+
+        let first = needed[0].min(source_height as usize);
+        let end = pending.last().map_or(first, |y| rows[*y].1.end());
+
+        RowResampler {
+            left: left as usize,
+            columns,
+            rows,
+            pending,
+            needed,
+            finished: 0,
+            horizontal: VecDeque::new(),
+            first,
+            end,
+            output: FloatImage::new(width, height),
+        }
+    }
+
+    /// The source rows to push, in order.
+    pub fn rows(&self) -> Range<usize> {
+        self.first..self.end
+    }
+
+    /// Pushes the next source rows. The rows of a block go through the
+    /// horizontal pass together, and the canvas rows that they finish through
+    /// the vertical pass together, both on the thread pool of the `parallel`
+    /// feature where it is enabled.
+    pub fn push_rows(&mut self, source_rows: &[Vec<[f32; 4]>]) {
+        let columns = &self.columns;
+        let left = self.left;
+        self.horizontal
+            .extend(parallel::map(source_rows, |source_row| {
+                horizontal_pass(columns, left, source_row)
+            }));
+
+        let pushed = self.first + self.horizontal.len();
+        let start = self.finished;
+
+        while self.finished < self.pending.len()
+            && self.rows[self.pending[self.finished]].1.end() <= pushed
+        {
+            self.finished += 1;
+        }
+
+        let width = self.columns.len();
+        let horizontal = &self.horizontal;
+        let first = self.first;
+        let rows = &self.rows;
+        let finished = parallel::map(&self.pending[start..self.finished], |y| {
+            let mut output_row = vec![[0.0; 4]; width];
+            vertical_pass(&rows[*y], columns, &mut output_row, |y| {
+                &horizontal[y - first]
+            });
+            output_row
+        });
+
+        for (y, output_row) in self.pending[start..self.finished].iter().zip(finished) {
+            self.output.pixels[y * width..(y + 1) * width].copy_from_slice(&output_row);
+        }
+
+        while self.first < self.needed[self.finished] && !self.horizontal.is_empty() {
+            self.horizontal.pop_front();
+            self.first += 1;
+        }
+    }
+
+    pub fn finish(self) -> FloatImage {
+        self.output
+    }
+}
+
+/// Resamples a source row, starting at column `left`, along the x axis.
+fn horizontal_pass(
+    columns: &[(f32, Weights)],
+    left: usize,
+    source_row: &[[f32; 4]],
+) -> Vec<[f32; 4]> {
+    let mut row = vec![[0.0; 4]; columns.len()];
+
+    for ((coverage, weights), pixel) in columns.iter().zip(row.iter_mut()) {
+        if *coverage > 0.0 {
+            let start = weights.start - left;
+            let source_pixels = &source_row[start..start + weights.values.len()];
+
+            accumulate_weighted(pixel, source_pixels, &weights.values, 1.0);
+        }
+    }
+
+    row
+}
+
+/// Resamples a canvas row along the y axis, from the source rows that
+/// `horizontal` returns after the horizontal pass.
+fn vertical_pass<'a>(
+    (row_coverage, weights): &(f32, Weights),
+    columns: &[(f32, Weights)],
+    output_row: &mut [[f32; 4]],
+    horizontal: impl Fn(usize) -> &'a [[f32; 4]],
+) {
+    for (i, weight) in weights.values.iter().enumerate() {
+        accumulate_row(output_row, horizontal(weights.start + i), *weight);
+    }
+
+    for (pixel, (column_coverage, _)) in output_row.iter_mut().zip(columns.iter()) {
+        scale(pixel, row_coverage * column_coverage);
+    }
+}
+
+fn resample_general(
+    source: &Window,
+    inverse: &Affine,
+    width: u32,
+    height: u32,
+    filter: Filter,
+) -> FloatImage {
+    // Source pixels spanned by one canvas pixel, along each source axis
+    let footprint_x = inverse.a.hypot(inverse.c);
+    let footprint_y = inverse.b.hypot(inverse.d);
+
+    let mut output = FloatImage::new(width, height);
+
+    parallel::for_each_row(&mut output.pixels, width as usize, |y, output_row| {
+        for (x, pixel) in output_row.iter_mut().enumerate() {
+            let (center_x, center_y) = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+
+            let pixel_coverage = coverage(center_x, footprint_x, source.source_width)
+                * coverage(center_y, footprint_y, source.source_height);
+
+            if pixel_coverage <= 0.0 {
+                continue;
+            }
+
+            let columns = axis_weights(center_x, footprint_x, source.source_width, filter);
+            let rows = axis_weights(center_y, footprint_y, source.source_height, filter);
+
+            for (j, row_weight) in rows.values.iter().enumerate() {
+                let source_row = source.row(rows.start + j, columns.start, columns.values.len());
+
+                accumulate_weighted(pixel, source_row, &columns.values, *row_weight);
+            }
+
+            scale(pixel, pixel_coverage);
+        }
+    });
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        box_reduce, linear_to_srgb, reduction_factor, resample, resample_window, srgb_to_linear,
+        visible_bounds, Affine, Filter, FloatImage, RowResampler, Window,
+    };
+    use image::{Rgba, RgbaImage};
+
+    fn gradient(width: u32, height: u32) -> FloatImage {
+        let mut image = FloatImage::new(width, height);
+
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            let x = (i as u32 % width) as f32 / (width - 1) as f32;
+            let y = (i as u32 / width) as f32 / (height - 1) as f32;
+            *pixel = [x, y, 0.5, 1.0];
+        }
+
+        image
+    }
+
+    #[test]
+    fn identity_keeps_pixels() {
+        let source = gradient(7, 5);
+
+        for filter in [
+            Filter::Nearest,
+            Filter::Triangle,
+            Filter::CatmullRom,
+            Filter::Lanczos3,
+        ]
+        .iter()
+        {
+            let output = resample(&source, &Affine::identity(), 7, 5, *filter);
+
+            for (a, b) in output.pixels.iter().zip(source.pixels.iter()) {
+                for channel in 0..4 {
+                    assert!((a[channel] - b[channel]).abs() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_srgb() {
+        for value in 0..256 {
+            let value = value as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn downscales_in_linear_light() {
+        let mut image = RgbaImage::new(2, 2);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            *pixel = Rgba {
+                data: [value, value, value, 255],
+            };
+        }
+
+        let srgb = resample(
+            &FloatImage::from_rgba(&image),
+            &Affine::scale(0.5, 0.5),
+            1,
+            1,
+            Filter::Triangle,
+        );
+        let mut linear = resample(
+            &FloatImage::from_rgba_linear(&image),
+            &Affine::scale(0.5, 0.5),
+            1,
+            1,
+            Filter::Triangle,
+        );
+        linear.linear_to_srgb();
+
+        assert_eq!(srgb.to_rgba().get_pixel(0, 0).data, [128, 128, 128, 255]);
+        assert_eq!(linear.to_rgba().get_pixel(0, 0).data, [188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn premultiplied_edges_keep_their_colour() {
+        let mut source = FloatImage::new(2, 1);
+        source.pixels = vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 0.0, 0.0]];
+
+        source.premultiply();
+        let mut output = resample(&source, &Affine::scale(0.5, 1.0), 1, 1, Filter::Triangle);
+        output.unpremultiply();
+
+        assert_eq!(output.pixels[0], [1.0, 0.0, 0.0, 0.5]);
+
+        output.premultiply();
+        output.fill_premultiplied([0.0, 0.0, 1.0, 1.0]);
+
+        assert_eq!(output.pixels[0], [0.5, 0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn fill_with_a_translucent_color() {
+        let mut image = FloatImage::new(2, 1);
+        image.pixels = vec![[0.5, 0.0, 0.0, 0.5], [0.0, 0.0, 0.0, 0.0]];
+
+        image.fill_premultiplied([0.0, 0.0, 1.0, 0.5]);
+
+        assert_eq!(image.pixels[0], [0.5, 0.0, 0.25, 0.75]);
+        assert_eq!(image.pixels[1], [0.0, 0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn nearest_keeps_hard_edges() {
+        let source = gradient(2, 2);
+        let output = resample(&source, &Affine::scale(4.0, 4.0), 8, 8, Filter::Nearest);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = source.pixels[(y / 4) * 2 + x / 4];
+                assert_eq!(output.pixels[y * 8 + x], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn chooses_filter_by_direction() {
+        assert_eq!(
+            Filter::for_affine(&Affine::scale(0.5, 0.5)),
+            Filter::Lanczos3
+        );
+        assert_eq!(
+            Filter::for_affine(&Affine::scale(2.0, 2.0).then(&Affine::quarter_turns(1))),
+            Filter::CatmullRom
+        );
+    }
+
+    #[test]
+    fn leaves_uncovered_canvas_transparent() {
+        let source = gradient(4, 4);
+        let output = resample(
+            &source,
+            &Affine::translate(2.0, 0.0),
+            8,
+            4,
+            Filter::Triangle,
+        );
+
+        assert_eq!(output.pixels[0], [0.0; 4]);
+        assert_eq!(output.pixels[1], [0.0; 4]);
+        assert_eq!(output.pixels[2][3], 1.0);
+        assert_eq!(output.pixels[6], [0.0; 4]);
+    }
+
+    #[test]
+    fn blends_sub_pixel_edges() {
+        let source = gradient(4, 4);
+        let output = resample(
+            &source,
+            &Affine::translate(0.25, 0.0),
+            5,
+            4,
+            Filter::Triangle,
+        );
+
+        assert!((output.pixels[0][3] - 0.75).abs() < 1e-6);
+        assert!((output.pixels[1][3] - 1.0).abs() < 1e-6);
+        assert!((output.pixels[4][3] - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn downscales_to_the_average() {
+        let mut source = FloatImage::new(4, 4);
+        for (i, pixel) in source.pixels.iter_mut().enumerate() {
+            *pixel = if i % 2 == 0 {
+                [1.0, 1.0, 1.0, 1.0]
+            } else {
+                [0.0, 0.0, 0.0, 1.0]
+            };
+        }
+
+        let output = resample(&source, &Affine::scale(0.25, 0.25), 1, 1, Filter::Triangle);
+
+        assert!((output.pixels[0][0] - 0.5).abs() < 1e-6);
+        assert!((output.pixels[0][3] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn quarter_turns_match_axis_aligned_sampling() {
+        let source = gradient(6, 6);
+
+        for scale in [1.0, 0.5].iter() {
+            let size = (6.0 * scale) as u32;
+            let center = Affine::translate(-3.0, -3.0);
+            let uncenter = Affine::translate(3.0 * scale, 3.0 * scale);
+
+            let rotated = resample(
+                &source,
+                &center
+                    .then(&Affine::quarter_turns(1))
+                    .then(&Affine::scale(*scale, *scale))
+                    .then(&uncenter),
+                size,
+                size,
+                Filter::Triangle,
+            );
+            let unrotated = resample(
+                &source,
+                &center.then(&Affine::scale(*scale, *scale)).then(&uncenter),
+                size,
+                size,
+                Filter::Triangle,
+            );
+
+            for y in 0..size {
+                for x in 0..size {
+                    let a = rotated.pixels[(y * size + x) as usize];
+                    let b = unrotated.pixels[((size - 1 - x) * size + y) as usize];
+
+                    for channel in 0..4 {
+                        assert!((a[channel] - b[channel]).abs() < 1e-5);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn visible_window_matches_whole_source() {
+        let mut source = gradient(40, 30);
+        for (i, pixel) in source.pixels.iter_mut().enumerate() {
+            pixel[2] = ((i * 7919) % 17) as f32 / 16.0;
+        }
+
+        let cases = [
+            (
+                Affine::scale(3.0, 3.0).then(&Affine::translate(-50.0, -20.0)),
+                Filter::Lanczos3,
+            ),
+            (
+                Affine::scale(0.5, 0.5).then(&Affine::translate(-7.0, -6.0)),
+                Filter::Lanczos3,
+            ),
+            (
+                Affine::scale(2.5, 2.5).then(&Affine::translate(-61.0, -7.5)),
+                Filter::Nearest,
+            ),
+            (
+                Affine::translate(-20.0, -15.0)
+                    .then(&Affine::quarter_turns(1))
+                    .then(&Affine::scale(2.0, 2.0))
+                    .then(&Affine::translate(8.0, 6.0)),
+                Filter::CatmullRom,
+            ),
+        ];
+
+        for (affine, filter) in cases.iter() {
+            let whole = resample(&source, affine, 16, 12, *filter);
+
+            let bounds = visible_bounds(40, 30, affine, 16, 12, *filter).unwrap();
+            assert!(bounds.width * bounds.height < 40 * 30);
+
+            let mut cropped = FloatImage::new(bounds.width, bounds.height);
+            for (i, pixel) in cropped.pixels.iter_mut().enumerate() {
+                let x = bounds.left as usize + i % bounds.width as usize;
+                let y = bounds.top as usize + i / bounds.width as usize;
+                *pixel = source.pixels[y * 40 + x];
+            }
+
+            let window = Window {
+                image: &cropped,
+                bounds,
+                source_width: 40,
+                source_height: 30,
+            };
+
+            assert!(whole.pixels == resample_window(&window, affine, 16, 12, *filter).pixels);
+        }
+    }
+
+    #[test]
+    fn pushed_rows_match_the_whole_source() {
+        let mut source = gradient(40, 30);
+        for (i, pixel) in source.pixels.iter_mut().enumerate() {
+            pixel[2] = ((i * 7919) % 17) as f32 / 16.0;
+        }
+
+        for affine in [Affine::scale(0.37, 0.53), Affine::scale(2.5, 1.5)] {
+            let whole = resample(&source, &affine, 16, 12, Filter::Lanczos3);
+            let inverse = affine.invert().unwrap();
+
+            // One row at a time, in the blocks that streaming pushes and all
+            // at once, which the `parallel` feature resamples on many threads
+            for block in [1, 3, 16, 30] {
+                let mut resampler =
+                    RowResampler::new(&inverse, 0, 40, 30, 16, 12, Filter::Lanczos3);
+                let rows: Vec<Vec<[f32; 4]>> = resampler
+                    .rows()
+                    .map(|y| source.pixels[y * 40..(y + 1) * 40].to_vec())
+                    .collect();
+
+                for pushed in rows.chunks(block) {
+                    resampler.push_rows(pushed);
+                }
+
+                assert!(resampler.finish().pixels == whole.pixels, "{}", block);
+            }
+        }
+    }
+
+    #[test]
+    fn box_reduces_by_large_ratios() {
+        assert_eq!(
+            reduction_factor(&Affine::identity(), Filter::Lanczos3, 6000, 4000),
+            1
+        );
+        assert_eq!(
+            reduction_factor(&Affine::scale(0.3, 0.3), Filter::Lanczos3, 6000, 4000),
+            1
+        );
+        assert_eq!(
+            reduction_factor(
+                &Affine::scale(1.0 / 60.0, 0.025),
+                Filter::Triangle,
+                6000,
+                4000
+            ),
+            20
+        );
+        assert_eq!(
+            reduction_factor(
+                &Affine::scale(1.0 / 60.0, 1.0 / 60.0),
+                Filter::Nearest,
+                6000,
+                4000
+            ),
+            1
+        );
+        assert_eq!(
+            reduction_factor(&Affine::scale(1e-9, 1e-9), Filter::Triangle, 30, 20),
+            30
+        );
+
+        let source = gradient(5, 3);
+        let reduced = box_reduce(&source, 2);
+
+        assert_eq!((reduced.width, reduced.height), (3, 2));
+        // A full block, and a partial one in the bottom right corner
+        assert!((reduced.pixels[0][0] - 0.125).abs() < 1e-6);
+        assert!((reduced.pixels[0][1] - 0.25).abs() < 1e-6);
+        assert!((reduced.pixels[5][0] - 1.0).abs() < 1e-6);
+        assert!((reduced.pixels[5][1] - 1.0).abs() < 1e-6);
+    }
+}
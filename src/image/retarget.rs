@@ -0,0 +1,560 @@
+use super::affine::Affine;
+use super::resample::{resample, Filter, FloatImage};
+use super::transform::{PixelSize, Region, Size};
+
+/// Added to the energy of protected pixels so that seams route around them.
+const PROTECTED_ENERGY: f32 = 1000.0;
+
+/// The largest share of a dimension that is removed as seams, beyond which
+/// seams are inserted along the other axis instead.
+const MAX_REMOVED: f32 = 0.5;
+
+/// The most that inserting seams grows a dimension by, beyond which the
+/// final resize stretches the rest.
+const MAX_INSERTED: f32 = 2.0;
+
+/// The sizes that retargeting a source towards a target works at.
+#[derive(PartialEq, Debug)]
+pub struct Plan {
+    /// The size that the source is resized to before carving, the smallest
+    /// that still has the carved size cover the target.
+    pub working: PixelSize,
+    /// The size after removing and inserting seams.
+    pub carved: PixelSize,
+}
+
+impl Plan {
+    pub fn new(source: &PixelSize, target: &Size) -> Self {
+        let ratio = target.height / target.width;
+        let (width, height) = carved_size(source.width, source.height, ratio);
+
+        let factor = (target.width / width as f32)
+            .max(target.height / height as f32)
+            .min(1.0);
+
+        let working = PixelSize {
+            width: ((source.width as f32 * factor).round() as u32).max(1),
+            height: ((source.height as f32 * factor).round() as u32).max(1),
+        };
+        let (width, height) = carved_size(working.width, working.height, ratio);
+
+        Plan {
+            working,
+            carved: PixelSize { width, height },
+        }
+    }
+
+    /// Roughly the pixels visited while carving, as finding each seam goes
+    /// over the whole working copy.
+    pub fn seam_pixels(&self) -> u64 {
+        let seams = u64::from(self.working.width.max(self.carved.width))
+            - u64::from(self.working.width.min(self.carved.width))
+            + u64::from(self.working.height.max(self.carved.height))
+            - u64::from(self.working.height.min(self.carved.height));
+
+        u64::from(self.working.width) * u64::from(self.working.height) * seams
+    }
+}
+
+/// Changes the aspect ratio of `source` towards that of `target` by removing
+/// or inserting low-energy seams, as laid out by `Plan`. The result still
+/// needs a final resize, which also stretches what the seams did not cover.
+pub fn retarget(source: &FloatImage, target: &Size, protected: &[Region]) -> FloatImage {
+    let plan = Plan::new(
+        &PixelSize {
+            width: source.width,
+            height: source.height,
+        },
+        target,
+    );
+    let working_width = plan.working.width;
+    let working_height = plan.working.height;
+
+    let working = resample(
+        source,
+        &Affine::scale(
+            working_width as f32 / source.width as f32,
+            working_height as f32 / source.height as f32,
+        ),
+        working_width,
+        working_height,
+        Filter::Triangle,
+    );
+    let mask = protection_mask(working_width, working_height, protected);
+
+    // Removing first leaves fewer pixels to find the inserted seams on
+    if plan.carved.width < working_width {
+        let (image, mask) = carve_columns(working, mask, plan.carved.width);
+        carve_rows(image, mask, plan.carved.height).0
+    } else {
+        let (image, mask) = carve_rows(working, mask, plan.carved.height);
+        carve_columns(image, mask, plan.carved.width).0
+    }
+}
+
+/// The size that carving `width` by `height` towards `ratio` (height over
+/// width) ends at.
+fn carved_size(width: u32, height: u32, ratio: f32) -> (u32, u32) {
+    if ratio > height as f32 / width as f32 {
+        narrow(width, height, ratio)
+    } else {
+        let (height, width) = narrow(height, width, 1.0 / ratio);
+        (width, height)
+    }
+}
+
+/// Narrows `length` to match `ratio` (the other side over `length`), removing
+/// at most `MAX_REMOVED` of it and then growing `other` by at most
+/// `MAX_INSERTED` times to make up the rest.
+fn narrow(length: u32, other: u32, ratio: f32) -> (u32, u32) {
+    let ideal = (other as f32 / ratio).round();
+    let minimum = (length as f32 * (1.0 - MAX_REMOVED)).round();
+
+    if ideal >= minimum {
+        ((ideal as u32).max(1), other)
+    } else {
+        let grown = (minimum * ratio).min(other as f32 * MAX_INSERTED);
+        ((minimum as u32).max(1), grown.round() as u32)
+    }
+}
+
+fn protection_mask(width: u32, height: u32, protected: &[Region]) -> Vec<bool> {
+    let mut mask = vec![false; (width as usize) * (height as usize)];
+
+    for region in protected {
+        let left = (region.x * width as f32).floor().max(0.0) as u32;
+        let top = (region.y * height as f32).floor().max(0.0) as u32;
+        let right = (((region.x + region.width) * width as f32).ceil() as u32).min(width);
+        let bottom = (((region.y + region.height) * height as f32).ceil() as u32).min(height);
+
+        for y in top..bottom {
+            for x in left..right {
+                mask[(y * width + x) as usize] = true;
+            }
+        }
+    }
+
+    mask
+}
+
+fn transpose<T: Copy>(values: &[T], width: u32, height: u32) -> Vec<T> {
+    let mut transposed = Vec::with_capacity(values.len());
+
+    for x in 0..width {
+        for y in 0..height {
+            transposed.push(values[(y * width + x) as usize]);
+        }
+    }
+
+    transposed
+}
+
+/// Removes or inserts horizontal seams until the image is `height` tall.
+fn carve_rows(image: FloatImage, mask: Vec<bool>, height: u32) -> (FloatImage, Vec<bool>) {
+    if height == image.height {
+        return (image, mask);
+    }
+
+    let transposed = FloatImage {
+        width: image.height,
+        height: image.width,
+        pixels: transpose(&image.pixels, image.width, image.height),
+    };
+    let transposed_mask = transpose(&mask, image.width, image.height);
+
+    let (carved, carved_mask) = carve_columns(transposed, transposed_mask, height);
+
+    (
+        FloatImage {
+            width: carved.height,
+            height: carved.width,
+            pixels: transpose(&carved.pixels, carved.width, carved.height),
+        },
+        transpose(&carved_mask, carved.width, carved.height),
+    )
+}
+
+/// Expects premultiplied pixels, so that transparent areas count as flat.
+fn luminance(pixel: &[f32; 4]) -> f32 {
+    0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2]
+}
+
+/// The luminance, protection and energy of an image that is narrowed one
+/// seam at a time. Removing a seam only changes the energy of the pixels
+/// next to it, so only those are computed again.
+struct Seams {
+    width: usize,
+    height: usize,
+    luma: Vec<f32>,
+    mask: Vec<bool>,
+    energy: Vec<f32>,
+}
+
+impl Seams {
+    fn new(image: &FloatImage, mask: Vec<bool>) -> Self {
+        let mut seams = Seams {
+            width: image.width as usize,
+            height: image.height as usize,
+            luma: image.pixels.iter().map(luminance).collect(),
+            mask,
+            energy: vec![0.0; image.pixels.len()],
+        };
+
+        for y in 0..seams.height {
+            for x in 0..seams.width {
+                seams.energy[y * seams.width + x] = seams.energy_at(x, y);
+            }
+        }
+
+        seams
+    }
+
+    fn energy_at(&self, x: usize, y: usize) -> f32 {
+        let width = self.width;
+        let luma = &self.luma;
+
+        let left = luma[y * width + x.saturating_sub(1)];
+        let right = luma[y * width + (x + 1).min(width - 1)];
+        let up = luma[y.saturating_sub(1) * width + x];
+        let down = luma[(y + 1).min(self.height - 1) * width + x];
+
+        let energy = (right - left).abs() + (down - up).abs();
+
+        if self.mask[y * width + x] {
+            energy + PROTECTED_ENERGY
+        } else {
+            energy
+        }
+    }
+
+    fn find(&self) -> Vec<usize> {
+        find_seam(&self.energy, self.width, self.height)
+    }
+
+    fn remove(&mut self, seam: &[usize]) {
+        self.luma = remove_seam(&self.luma, self.width, seam);
+        self.mask = remove_seam(&self.mask, self.width, seam);
+        self.energy = remove_seam(&self.energy, self.width, seam);
+        self.width -= 1;
+
+        if self.width == 0 {
+            return;
+        }
+
+        // Seams move by at most one column a row, so the pixels whose
+        // horizontal or vertical neighbours changed are within this strip
+        for (y, &x) in seam.iter().enumerate() {
+            let right = (x + 2).min(self.width);
+            for x in x.saturating_sub(2)..right {
+                self.energy[y * self.width + x] = self.energy_at(x, y);
+            }
+        }
+    }
+}
+
+/// Finds the connected top-to-bottom path of least energy, one column per row.
+fn find_seam(energy: &[f32], width: usize, height: usize) -> Vec<usize> {
+    let mut cumulative = energy[..width].to_vec();
+    let mut previous = vec![0; width * height];
+
+    for y in 1..height {
+        let row = &energy[y * width..(y + 1) * width];
+        let mut next = vec![0.0; width];
+
+        for x in 0..width {
+            let mut best = x;
+
+            if x > 0 && cumulative[x - 1] < cumulative[best] {
+                best = x - 1;
+            }
+            if x + 1 < width && cumulative[x + 1] < cumulative[best] {
+                best = x + 1;
+            }
+
+            next[x] = row[x] + cumulative[best];
+            previous[y * width + x] = best;
+        }
+
+        cumulative = next;
+    }
+
+    let mut x = (0..width)
+        .min_by(|a, b| cumulative[*a].partial_cmp(&cumulative[*b]).unwrap())
+        .unwrap_or(0);
+
+    let mut seam = vec![0; height];
+    for y in (0..height).rev() {
+        seam[y] = x;
+        x = previous[y * width + x];
+    }
+
+    seam
+}
+
+fn remove_seam<T: Copy>(values: &[T], width: usize, seam: &[usize]) -> Vec<T> {
+    let mut output = Vec::with_capacity(values.len() - seam.len());
+
+    for (y, row) in values.chunks(width).enumerate() {
+        output.extend_from_slice(&row[..seam[y]]);
+        output.extend_from_slice(&row[seam[y] + 1..]);
+    }
+
+    output
+}
+
+/// Removes or inserts vertical seams until the image is `width` wide.
+fn carve_columns(image: FloatImage, mask: Vec<bool>, width: u32) -> (FloatImage, Vec<bool>) {
+    if width < image.width {
+        remove_columns(image, mask, width)
+    } else if width > image.width {
+        insert_columns(image, mask, width)
+    } else {
+        (image, mask)
+    }
+}
+
+fn remove_columns(mut image: FloatImage, mask: Vec<bool>, width: u32) -> (FloatImage, Vec<bool>) {
+    let mut seams = Seams::new(&image, mask);
+
+    while image.width > width {
+        let seam = seams.find();
+
+        image.pixels = remove_seam(&image.pixels, image.width as usize, &seam);
+        seams.remove(&seam);
+        image.width -= 1;
+    }
+
+    (image, seams.mask)
+}
+
+/// Duplicates the seams that would be removed first, blending each copy with
+/// its right-hand neighbour so that the inserted columns do not stand out.
+fn insert_columns(image: FloatImage, mask: Vec<bool>, width: u32) -> (FloatImage, Vec<bool>) {
+    let original_width = image.width as usize;
+    let height = image.height as usize;
+    let count = (width - image.width) as usize;
+
+    // Seams are found on a shrinking copy, so keep track of original columns.
+    let mut columns: Vec<usize> = (0..height).flat_map(|_| 0..original_width).collect();
+    let mut shrinking = Seams::new(&image, mask.clone());
+    let mut duplicates = vec![0u32; original_width * height];
+
+    for _ in 0..count.min(original_width) {
+        let current_width = shrinking.width;
+        let seam = shrinking.find();
+
+        for (y, x) in seam.iter().enumerate() {
+            duplicates[y * original_width + columns[y * current_width + x]] += 1;
+        }
+
+        shrinking.remove(&seam);
+        columns = remove_seam(&columns, current_width, &seam);
+
+        if shrinking.width == 0 {
+            break;
+        }
+    }
+
+    let mut output = FloatImage::new(
+        image.width + duplicates[..original_width].iter().sum::<u32>(),
+        image.height,
+    );
+    let mut output_mask = Vec::with_capacity((output.width as usize) * height);
+    output.pixels.clear();
+
+    for y in 0..height {
+        for x in 0..original_width {
+            let i = y * original_width + x;
+            let pixel = image.pixels[i];
+
+            output.pixels.push(pixel);
+            output_mask.push(mask[i]);
+
+            let neighbour = image.pixels[y * original_width + (x + 1).min(original_width - 1)];
+            for _ in 0..duplicates[i] {
+                output.pixels.push([
+                    (pixel[0] + neighbour[0]) / 2.0,
+                    (pixel[1] + neighbour[1]) / 2.0,
+                    (pixel[2] + neighbour[2]) / 2.0,
+                    (pixel[3] + neighbour[3]) / 2.0,
+                ]);
+                output_mask.push(mask[i]);
+            }
+        }
+    }
+
+    if output.width < width {
+        carve_columns(output, output_mask, width)
+    } else {
+        (output, output_mask)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{carve_columns, remove_seam, retarget, FloatImage, Plan, Region, Seams, Size};
+    use crate::image::transform::PixelSize;
+
+    /// A flat grey image with a bright vertical bar at `bar`.
+    fn bar_image(width: u32, height: u32, bar: u32) -> FloatImage {
+        let mut image = FloatImage::new(width, height);
+
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = if i as u32 % width == bar {
+                [1.0, 1.0, 1.0, 1.0]
+            } else {
+                [0.5, 0.5, 0.5, 1.0]
+            };
+        }
+
+        image
+    }
+
+    fn bar_columns(image: &FloatImage) -> Vec<u32> {
+        (0..image.width)
+            .filter(|x| image.pixels[*x as usize][0] > 0.9)
+            .collect()
+    }
+
+    #[test]
+    fn removes_low_energy_seams() {
+        let image = bar_image(20, 10, 12);
+        let mask = vec![false; 200];
+
+        let (carved, _) = carve_columns(image, mask, 10);
+
+        assert_eq!(carved.width, 10);
+        assert_eq!(carved.pixels.len(), 100);
+        assert_eq!(bar_columns(&carved).len(), 1);
+    }
+
+    #[test]
+    fn inserts_seams_to_widen() {
+        let image = bar_image(10, 10, 4);
+        let mask = vec![false; 100];
+
+        let (carved, carved_mask) = carve_columns(image, mask, 16);
+
+        assert_eq!(carved.width, 16);
+        assert_eq!(carved.pixels.len(), 160);
+        assert_eq!(carved_mask.len(), 160);
+        assert_eq!(bar_columns(&carved).len(), 1);
+    }
+
+    #[test]
+    fn retargets_to_the_requested_ratio() {
+        let image = bar_image(40, 20, 30);
+
+        let carved = retarget(
+            &image,
+            &Size {
+                width: 20.0,
+                height: 20.0,
+            },
+            &[],
+        );
+
+        assert_eq!((carved.width, carved.height), (20, 20));
+        assert_eq!(bar_columns(&carved).len(), 1);
+
+        let carved = retarget(
+            &image,
+            &Size {
+                width: 10.0,
+                height: 20.0,
+            },
+            &[],
+        );
+
+        // Carved on a copy half the size, which is as small as still covers
+        // the target
+        assert_eq!((carved.width, carved.height), (10, 20));
+    }
+
+    #[test]
+    fn plans_a_small_working_copy_and_caps_insertion() {
+        let plan = Plan::new(
+            &PixelSize {
+                width: 4000,
+                height: 3000,
+            },
+            &Size {
+                width: 4000.0,
+                height: 100.0,
+            },
+        );
+
+        // Half the rows are removed and the columns only doubled, which the
+        // final resize stretches the rest of the way
+        assert_eq!(
+            plan,
+            Plan {
+                working: PixelSize {
+                    width: 2000,
+                    height: 1500,
+                },
+                carved: PixelSize {
+                    width: 4000,
+                    height: 750,
+                },
+            }
+        );
+        assert_eq!(plan.seam_pixels(), 2000 * 1500 * (2000 + 750));
+    }
+
+    #[test]
+    fn updates_energy_next_to_removed_seams() {
+        let mut image = bar_image(12, 8, 5);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            pixel[1] = (i * 7 % 11) as f32 / 11.0;
+        }
+        let mut mask = vec![false; 96];
+        mask[30] = true;
+
+        let mut seams = Seams::new(&image, mask);
+        for _ in 0..5 {
+            let seam = seams.find();
+            image.pixels = remove_seam(&image.pixels, image.width as usize, &seam);
+            image.width -= 1;
+            seams.remove(&seam);
+
+            assert_eq!(seams.energy, Seams::new(&image, seams.mask.clone()).energy);
+        }
+    }
+
+    #[test]
+    fn keeps_protected_regions() {
+        let mut image = FloatImage::new(20, 10);
+        for pixel in image.pixels.iter_mut() {
+            *pixel = [0.5, 0.5, 0.5, 1.0];
+        }
+        // A flat red block that would otherwise be as cheap to remove as the
+        // background around it.
+        for y in 0..10 {
+            for x in 2..6 {
+                image.pixels[y * 20 + x] = [1.0, 0.0, 0.0, 1.0];
+            }
+        }
+
+        let carved = retarget(
+            &image,
+            &Size {
+                width: 10.0,
+                height: 10.0,
+            },
+            &[Region {
+                x: 0.0,
+                y: 0.0,
+                width: 0.35,
+                height: 1.0,
+            }],
+        );
+
+        let red = carved.pixels[..10]
+            .iter()
+            .filter(|pixel| pixel[0] > 0.9 && pixel[1] < 0.1)
+            .count();
+
+        assert_eq!(red, 4);
+    }
+}
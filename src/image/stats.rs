@@ -0,0 +1,210 @@
+use super::adjust::luminance;
+
+use image::DynamicImage;
+
+/// Figures about the pixels of an image, for checking the results of the
+/// pipeline rather than for changing them.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Stats {
+    /// The color channels, over the visible pixels only.
+    pub red: Channel,
+    pub green: Channel,
+    pub blue: Channel,
+    /// Over every pixel, or `None` for images without alpha.
+    pub alpha: Option<Channel>,
+    /// The variance of the Laplacian of the luminance, which is higher for
+    /// sharper images and 0 for flat ones.
+    pub sharpness: f32,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Channel {
+    /// The pixel count of each of the 256 values.
+    pub histogram: Vec<u32>,
+    pub mean: f32,
+    pub std_dev: f32,
+    /// The percentages of the pixels at 0 and at 255.
+    pub clipped_shadows: f32,
+    pub clipped_highlights: f32,
+}
+
+impl Channel {
+    fn from_histogram(histogram: [u32; 256]) -> Self {
+        let count: u64 = histogram.iter().map(|&n| u64::from(n)).sum();
+        if count == 0 {
+            return Channel {
+                histogram: histogram.to_vec(),
+                mean: 0.0,
+                std_dev: 0.0,
+                clipped_shadows: 0.0,
+                clipped_highlights: 0.0,
+            };
+        }
+
+        let count = count as f64;
+        let mean = histogram
+            .iter()
+            .enumerate()
+            .map(|(value, &n)| value as f64 * f64::from(n))
+            .sum::<f64>()
+            / count;
+        let variance = histogram
+            .iter()
+            .enumerate()
+            .map(|(value, &n)| (value as f64 - mean).powi(2) * f64::from(n))
+            .sum::<f64>()
+            / count;
+
+        Channel {
+            histogram: histogram.to_vec(),
+            mean: mean as f32,
+            std_dev: variance.sqrt() as f32,
+            clipped_shadows: (f64::from(histogram[0]) * 100.0 / count) as f32,
+            clipped_highlights: (f64::from(histogram[255]) * 100.0 / count) as f32,
+        }
+    }
+}
+
+pub fn compute(image: &DynamicImage) -> Stats {
+    let rgba = image.to_rgba();
+    let mut histograms = [[0u32; 256]; 4];
+
+    for pixel in rgba.pixels() {
+        let [red, green, blue, alpha] = pixel.data;
+        histograms[3][usize::from(alpha)] += 1;
+
+        if alpha > 0 {
+            histograms[0][usize::from(red)] += 1;
+            histograms[1][usize::from(green)] += 1;
+            histograms[2][usize::from(blue)] += 1;
+        }
+    }
+
+    let has_alpha = matches!(
+        image,
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgba8(_) | DynamicImage::ImageBgra8(_)
+    );
+
+    Stats {
+        red: Channel::from_histogram(histograms[0]),
+        green: Channel::from_histogram(histograms[1]),
+        blue: Channel::from_histogram(histograms[2]),
+        alpha: if has_alpha {
+            Some(Channel::from_histogram(histograms[3]))
+        } else {
+            None
+        },
+        sharpness: sharpness(&rgba),
+    }
+}
+
+/// The variance of the 4-neighbour Laplacian of the luminance, in levels,
+/// over black so that edges of transparent areas count as edges.
+fn sharpness(rgba: &image::RgbaImage) -> f32 {
+    let (width, height) = rgba.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let luma: Vec<f32> = rgba
+        .pixels()
+        .map(|pixel| {
+            let [red, green, blue, alpha] = pixel.data;
+            let rgb = [
+                f32::from(red) / 255.0,
+                f32::from(green) / 255.0,
+                f32::from(blue) / 255.0,
+            ];
+            luminance(rgb) * f32::from(alpha)
+        })
+        .collect();
+
+    let width = width as usize;
+    let height = height as usize;
+    let mut sum = 0.0;
+    let mut sum_of_squares = 0.0;
+
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let i = y * width + x;
+            let laplacian = f64::from(
+                luma[i - width] + luma[i + width] + luma[i - 1] + luma[i + 1] - 4.0 * luma[i],
+            );
+            sum += laplacian;
+            sum_of_squares += laplacian * laplacian;
+        }
+    }
+
+    let count = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / count;
+
+    (sum_of_squares / count - mean * mean).max(0.0) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::compute;
+    use image::DynamicImage;
+
+    #[test]
+    fn computes_channel_figures() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(10, 10, |x, _| image::Rgb {
+            data: if x < 5 {
+                [0, 100, 255]
+            } else {
+                [255, 100, 255]
+            },
+        }));
+
+        let stats = compute(&image);
+
+        assert_eq!(stats.red.histogram[0], 50);
+        assert_eq!(stats.red.histogram[255], 50);
+        assert_eq!(stats.red.mean, 127.5);
+        assert_eq!(stats.red.std_dev, 127.5);
+        assert_eq!(stats.red.clipped_shadows, 50.0);
+        assert_eq!(stats.red.clipped_highlights, 50.0);
+        assert_eq!(stats.green.mean, 100.0);
+        assert_eq!(stats.green.std_dev, 0.0);
+        assert_eq!(stats.green.clipped_shadows, 0.0);
+        assert_eq!(stats.blue.clipped_highlights, 100.0);
+        assert_eq!(stats.alpha, None);
+    }
+
+    #[test]
+    fn counts_only_visible_pixels_in_color_channels() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(4, 4, |x, _| image::Rgba {
+            data: if x == 0 {
+                [0, 0, 0, 0]
+            } else {
+                [200, 200, 200, 255]
+            },
+        }));
+
+        let stats = compute(&image);
+
+        assert_eq!(stats.red.mean, 200.0);
+        assert_eq!(stats.red.histogram.iter().sum::<u32>(), 12);
+
+        let alpha = stats.alpha.unwrap();
+        assert_eq!(alpha.clipped_shadows, 25.0);
+        assert_eq!(alpha.clipped_highlights, 75.0);
+    }
+
+    #[test]
+    fn estimates_sharpness() {
+        let flat = image::GrayImage::from_pixel(16, 16, image::Luma { data: [128] });
+        let edge = image::GrayImage::from_fn(16, 16, |x, _| image::Luma {
+            data: [if x < 8 { 0 } else { 255 }],
+        });
+        let blurred = image::imageops::blur(&edge, 2.0);
+
+        let flat = compute(&DynamicImage::ImageLuma8(flat)).sharpness;
+        let edge = compute(&DynamicImage::ImageLuma8(edge)).sharpness;
+        let blurred = compute(&DynamicImage::ImageLuma8(blurred)).sharpness;
+
+        assert_eq!(flat, 0.0);
+        assert!(edge > blurred, "{} {}", edge, blurred);
+        assert!(blurred > 0.0);
+    }
+}
@@ -0,0 +1,310 @@
+use super::affine::Affine;
+use super::decode;
+use super::resample::{self, Filter, FloatImage, Reduction, RowResampler};
+use super::transform::PixelSize;
+
+use image::{DynamicImage, GenericImageView};
+use png::HasParameters;
+
+/// Source rows that are resampled together, which bounds the rows held on top
+/// of the ones that canvas rows still need.
+const PUSHED_ROWS: usize = 16;
+
+/// Decoded source rows of 8-bit RGBA, read from the top down.
+pub trait Rows {
+    fn size(&self) -> PixelSize;
+
+    /// Reads the next row, which has `size().width` pixels.
+    fn read_row(&mut self, row: &mut [[u8; 4]]) -> Result<(), failure::Error>;
+}
+
+/// Rows of a PNG, decoded one at a time.
+pub struct PngRows<'a> {
+    reader: png::Reader<&'a [u8]>,
+    color_type: png::ColorType,
+    /// The row with 16 bit samples rounded to 8 bits, if they have 16.
+    rounded: Option<Vec<u8>>,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> PngRows<'a> {
+    /// Returns `None` for interlaced images, which arrive in passes over the
+    /// whole image rather than row by row.
+    pub fn new(buffer: &'a [u8]) -> Result<Option<Self>, failure::Error> {
+        // The decoder would cut 16 bit samples off rather than round them
+        let mut decoder = png::Decoder::new(buffer);
+        decoder.set(png::Transformations::EXPAND);
+        let (info, reader) = decoder.read_info()?;
+
+        if reader.info().interlaced {
+            return Ok(None);
+        }
+
+        let rounded = if reader.info().bit_depth == png::BitDepth::Sixteen {
+            Some(Vec::new())
+        } else {
+            None
+        };
+
+        Ok(Some(PngRows {
+            reader,
+            color_type: info.color_type,
+            rounded,
+            width: info.width,
+            height: info.height,
+        }))
+    }
+}
+
+impl<'a> Rows for PngRows<'a> {
+    fn size(&self) -> PixelSize {
+        PixelSize {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn read_row(&mut self, row: &mut [[u8; 4]]) -> Result<(), failure::Error> {
+        let mut data = self
+            .reader
+            .next_row()?
+            .ok_or_else(|| failure::format_err!("image data ended early"))?;
+
+        if let Some(rounded) = &mut self.rounded {
+            rounded.clear();
+            rounded.extend(decode::round_16(data));
+            data = rounded;
+        }
+
+        // The decoder expands palettes, low bit depths and tRNS transparency
+        let channels = match self.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => {
+                return Err(failure::format_err!("unexpected indexed image data"));
+            }
+        };
+        expand_row(row, data, channels);
+
+        Ok(())
+    }
+}
+
+/// Rows of an image that has already been decoded, such as a JPEG decoded
+/// at a reduced scale, which is held in memory whole.
+pub struct ImageRows {
+    data: Vec<u8>,
+    channels: usize,
+    width: u32,
+    height: u32,
+    y: usize,
+}
+
+impl ImageRows {
+    pub fn new(image: DynamicImage) -> Self {
+        let width = image.width();
+        let height = image.height();
+        let (data, channels) = match image {
+            DynamicImage::ImageLuma8(image) => (image.into_raw(), 1),
+            DynamicImage::ImageLumaA8(image) => (image.into_raw(), 2),
+            DynamicImage::ImageRgb8(image) => (image.into_raw(), 3),
+            DynamicImage::ImageRgba8(image) => (image.into_raw(), 4),
+            image => (image.to_rgba().into_raw(), 4),
+        };
+
+        ImageRows {
+            data,
+            channels,
+            width,
+            height,
+            y: 0,
+        }
+    }
+}
+
+impl Rows for ImageRows {
+    fn size(&self) -> PixelSize {
+        PixelSize {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn read_row(&mut self, row: &mut [[u8; 4]]) -> Result<(), failure::Error> {
+        let length = self.width as usize * self.channels;
+        let data = self
+            .data
+            .get(self.y * length..(self.y + 1) * length)
+            .ok_or_else(|| failure::format_err!("image data ended early"))?;
+
+        expand_row(row, data, self.channels);
+        self.y += 1;
+
+        Ok(())
+    }
+}
+
+/// Widens a row of grey, grey and alpha, RGB or RGBA samples to RGBA.
+fn expand_row(row: &mut [[u8; 4]], data: &[u8], channels: usize) {
+    for (pixel, value) in row.iter_mut().zip(data.chunks(channels)) {
+        *pixel = match channels {
+            1 => [value[0], value[0], value[0], 255],
+            2 => [value[0], value[0], value[0], value[1]],
+            3 => [value[0], value[1], value[2], 255],
+            _ => [value[0], value[1], value[2], value[3]],
+        };
+    }
+}
+
+/// Box reduces and resamples `rows` onto a `width` x `height` canvas like
+/// `process` does, with the same output. Each source row is read once and
+/// dropped as soon as no canvas row needs it, so that memory use follows the
+/// canvas size rather than the source size, apart from whatever `rows` holds
+/// itself. Rows are resampled in blocks of `PUSHED_ROWS`, in parallel with the
+/// `parallel` feature. `affine` must be axis aligned.
+pub fn resample_rows(
+    rows: &mut dyn Rows,
+    affine: &Affine,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    linear: bool,
+) -> Result<FloatImage, failure::Error> {
+    let size = rows.size();
+    let reduction = Reduction::new(size.width, size.height, affine, width, height, filter);
+
+    let inverse = match reduction.affine.invert() {
+        Some(inverse) => inverse,
+        None => return Ok(FloatImage::new(width, height)),
+    };
+
+    if !inverse.is_axis_aligned() {
+        return Err(failure::format_err!(
+            "rows can only be resampled along the axes"
+        ));
+    }
+
+    if reduction.bounds.width == 0 || reduction.bounds.height == 0 {
+        return Ok(FloatImage::new(width, height));
+    }
+
+    let mut resampler = RowResampler::new(
+        &inverse,
+        reduction.bounds.left,
+        reduction.width,
+        reduction.height,
+        width,
+        height,
+        filter,
+    );
+    let needed = resampler.rows();
+
+    let factor = reduction.factor as usize;
+    let visible = reduction.source_bounds();
+    let left = visible.left as usize;
+    let right = left + visible.width as usize;
+    let table = resample::channel_table(linear);
+
+    let mut row = vec![[0; 4]; size.width as usize];
+    let mut float_row = vec![[0.0; 4]; visible.width as usize];
+    let mut reduced_row = vec![[0.0; 4]; reduction.bounds.width as usize];
+    let mut block_rows = 0;
+    let mut pushed = Vec::with_capacity(PUSHED_ROWS);
+
+    for y in 0..(needed.end * factor).min(size.height as usize) {
+        rows.read_row(&mut row)?;
+
+        if y / factor < needed.start {
+            continue;
+        }
+
+        for (pixel, source_pixel) in float_row.iter_mut().zip(row[left..right].iter()) {
+            *pixel = resample::to_float(&table, *source_pixel);
+            resample::premultiply(pixel);
+        }
+
+        if factor == 1 {
+            pushed.push(float_row.clone());
+        } else {
+            resample::box_accumulate(&mut reduced_row, &float_row, reduction.factor);
+            block_rows += 1;
+
+            if (y + 1) % factor == 0 || y + 1 == size.height as usize {
+                resample::box_average(
+                    &mut reduced_row,
+                    visible.width as usize,
+                    block_rows,
+                    reduction.factor,
+                );
+                let width = reduced_row.len();
+                pushed.push(std::mem::replace(&mut reduced_row, vec![[0.0; 4]; width]));
+                block_rows = 0;
+            }
+        }
+
+        if pushed.len() == PUSHED_ROWS {
+            resampler.push_rows(&pushed);
+            pushed.clear();
+        }
+    }
+
+    resampler.push_rows(&pushed);
+
+    Ok(resampler.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ImageRows, PngRows, Rows};
+    use image::{DynamicImage, GenericImageView};
+
+    #[test]
+    fn reads_png_rows() {
+        for path in ["./tests/input/test_pattern.png", "./tests/input/simple.png"].iter() {
+            let buffer = std::fs::read(path).unwrap();
+            let image = image::load_from_memory(&buffer).unwrap();
+            let mut rows = PngRows::new(&buffer).unwrap().unwrap();
+
+            assert_eq!(rows.size().width, image.width());
+            assert_eq!(rows.size().height, image.height());
+
+            let mut row = vec![[0; 4]; image.width() as usize];
+            for y in 0..image.height() {
+                rows.read_row(&mut row).unwrap();
+
+                for (x, pixel) in row.iter().enumerate() {
+                    assert_eq!(*pixel, image.get_pixel(x as u32, y).data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_image_rows() {
+        let rgb = image::open("./tests/input/test_pattern.png")
+            .unwrap()
+            .to_rgb();
+        let images = [
+            DynamicImage::ImageRgb8(rgb.clone()),
+            DynamicImage::ImageLuma8(DynamicImage::ImageRgb8(rgb.clone()).to_luma()),
+            DynamicImage::ImageBgra8(DynamicImage::ImageRgb8(rgb).to_bgra()),
+        ];
+
+        for image in images.iter() {
+            let mut rows = ImageRows::new(image.clone());
+            let mut row = vec![[0; 4]; image.width() as usize];
+
+            for y in 0..image.height() {
+                rows.read_row(&mut row).unwrap();
+
+                for (x, pixel) in row.iter().enumerate() {
+                    assert_eq!(*pixel, image.get_pixel(x as u32, y).data);
+                }
+            }
+            assert!(rows.read_row(&mut row).is_err());
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use super::affine::{Affine, Crop};
+
 #[derive(PartialEq, Debug)]
 pub enum TransformMode {
     Fill { width: u32, height: u32 },
@@ -5,6 +7,25 @@ pub enum TransformMode {
     FitWidth(u32),
     FitHeight(u32),
     Limit { width: u32, height: u32 },
+    Retarget { width: u32, height: u32 },
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Length {
+    /// Resolves the length against the source, to at least 1 pixel.
+    pub fn to_pixels(self, source_pixels: u32) -> u32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => {
+                ((source_pixels as f32 * percent / 100.0).round() as u32).max(1)
+            }
+        }
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -25,6 +46,15 @@ pub struct Offset {
     pub dy: f32,
 }
 
+/// A rectangle relative to the source size, from `0.0` to `1.0`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Dimensions {
     pub size: Size,
@@ -50,153 +80,305 @@ pub struct PixelDimensions {
     pub origin: PixelCoords,
 }
 
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Rotation {
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation {
+    fn quarter_turns(self) -> u32 {
+        match self {
+            Rotation::None => 0,
+            Rotation::Clockwise90 => 1,
+            Rotation::Clockwise180 => 2,
+            Rotation::Clockwise270 => 3,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+}
+
 pub struct Transform {
-    input_size: Size,
-    canvas_size: Size,
+    source_size: Size,
     mode: TransformMode,
     pub relative_center_offset: Offset,
     pub scale: f32,
+    pub rotation: Rotation,
+    pub flip: Flip,
+    pub protected_regions: Vec<Region>,
 }
 
 impl Transform {
     pub fn new(input_pixel_size: &PixelSize, mode: TransformMode) -> Self {
-        let input_size = Size {
-            width: input_pixel_size.width as f32,
-            height: input_pixel_size.height as f32,
-        };
-
-        let input_ratio = input_size.height / input_size.width;
-
-        let canvas_size = match mode {
-            TransformMode::Fill { width, height } | TransformMode::Fit { width, height } => Size {
-                width: width as f32,
-                height: height as f32,
-            },
-            TransformMode::FitWidth(width) => Size {
-                width: width as f32,
-                height: (width as f32) * input_ratio,
-            },
-            TransformMode::FitHeight(height) => Size {
-                width: (height as f32) / input_ratio,
-                height: height as f32,
-            },
-            TransformMode::Limit { width, height } => Size {
-                width: ((height as f32) / input_ratio).min(width as f32),
-                height: ((width as f32) * input_ratio).min(height as f32),
-            },
-        };
-
         Transform {
-            input_size: input_size,
-            canvas_size: canvas_size,
-            mode: mode,
+            source_size: Size {
+                width: input_pixel_size.width as f32,
+                height: input_pixel_size.height as f32,
+            },
+            mode,
             relative_center_offset: Offset { dx: 0.0, dy: 0.0 },
             scale: 1.0,
+            rotation: Rotation::None,
+            flip: Flip {
+                horizontal: false,
+                vertical: false,
+            },
+            protected_regions: Vec::new(),
         }
     }
 
-    fn get_output_size(&self) -> Size {
-        let input_size = &self.input_size;
-        let input_ratio = input_size.height / input_size.width;
+    /// The size that the source is stretched to when retargeting, in the
+    /// orientation of the source.
+    pub fn get_retarget_size(&self) -> Option<Size> {
+        match self.mode {
+            TransformMode::Retarget { width, height } => Some(match self.rotation {
+                Rotation::Clockwise90 | Rotation::Clockwise270 => Size {
+                    width: height as f32,
+                    height: width as f32,
+                },
+                Rotation::None | Rotation::Clockwise180 => Size {
+                    width: width as f32,
+                    height: height as f32,
+                },
+            }),
+            _ => None,
+        }
+    }
 
-        let canvas_size = &self.canvas_size;
-        let canvas_ratio = canvas_size.height / canvas_size.width;
+    /// The source size as it appears after rotation.
+    fn get_input_size(&self) -> Size {
+        match self.rotation {
+            Rotation::Clockwise90 | Rotation::Clockwise270 => Size {
+                width: self.source_size.height,
+                height: self.source_size.width,
+            },
+            Rotation::None | Rotation::Clockwise180 => Size {
+                width: self.source_size.width,
+                height: self.source_size.height,
+            },
+        }
+    }
 
-        let mut output_size = Size {
-            width: 0.0,
-            height: 0.0,
-        };
+    /// The scale from the rotated source to the canvas along each axis of
+    /// the canvas, before `scale`.
+    fn get_mode_scale(&self) -> (f32, f32) {
+        let input_size = self.get_input_size();
 
-        match &self.mode {
-            TransformMode::Fill {
-                width: _,
-                height: _,
-            } => {
-                if canvas_ratio > input_ratio {
-                    output_size.height = canvas_size.height
-                } else {
-                    output_size.width = canvas_size.width
-                }
+        match self.mode {
+            TransformMode::Fill { width, height } => {
+                let scale =
+                    (width as f32 / input_size.width).max(height as f32 / input_size.height);
+                (scale, scale)
             }
-            _ => {
-                if input_ratio < 1.0 && input_ratio < canvas_ratio {
-                    output_size.width = canvas_size.width
-                } else {
-                    output_size.height = canvas_size.height
-                }
+            TransformMode::Fit { width, height } | TransformMode::Limit { width, height } => {
+                let scale =
+                    (width as f32 / input_size.width).min(height as f32 / input_size.height);
+                (scale, scale)
+            }
+            TransformMode::FitWidth(width) => {
+                let scale = width as f32 / input_size.width;
+                (scale, scale)
             }
+            TransformMode::FitHeight(height) => {
+                let scale = height as f32 / input_size.height;
+                (scale, scale)
+            }
+            TransformMode::Retarget { width, height } => (
+                width as f32 / input_size.width,
+                height as f32 / input_size.height,
+            ),
         }
+    }
 
-        if output_size.height > 0.0 {
-            let ratio = output_size.height / input_size.height;
+    /// Flips, rotates and scales the source about the origin to how it is
+    /// placed on the canvas, with the mode scale multiplied by `scale`.
+    fn get_orientation(&self, scale: f32) -> Affine {
+        let (sx, sy) = self.get_mode_scale();
 
-            output_size.width = ratio * input_size.width;
-        } else {
-            let ratio = output_size.width / input_size.width;
+        let flip = Affine::scale(
+            if self.flip.horizontal { -1.0 } else { 1.0 },
+            if self.flip.vertical { -1.0 } else { 1.0 },
+        );
 
-            output_size.height = ratio * input_size.height;
-        }
+        flip.then(&Affine::quarter_turns(self.rotation.quarter_turns()))
+            .then(&Affine::scale(sx * scale, sy * scale))
+    }
 
-        output_size.width = output_size.width * self.scale;
-        output_size.height = output_size.height * self.scale;
+    /// The size of the source once mapped by `affine`.
+    fn map_source_size(&self, affine: &Affine) -> Size {
+        let (_, _, width, height) =
+            affine.map_rect(0.0, 0.0, self.source_size.width, self.source_size.height);
 
-        output_size
+        Size { width, height }
     }
 
-    fn get_output_origin(&self, output_size: &Size) -> Coords {
-        let center = Coords {
-            x: output_size.width / 2.0,
-            y: output_size.height / 2.0,
-        };
+    /// The canvas size that the mode asks for, or else the size of the
+    /// source at the mode scale.
+    fn get_canvas_size(&self) -> Size {
+        match self.mode {
+            TransformMode::Fill { width, height }
+            | TransformMode::Fit { width, height }
+            | TransformMode::Retarget { width, height } => Size {
+                width: width as f32,
+                height: height as f32,
+            },
+            TransformMode::FitWidth(_)
+            | TransformMode::FitHeight(_)
+            | TransformMode::Limit { .. } => self.map_source_size(&self.get_orientation(1.0)),
+        }
+    }
 
-        let canvas_center = Coords {
-            x: self.canvas_size.width / 2.0,
-            y: self.canvas_size.height / 2.0,
-        };
+    fn get_output_size(&self) -> Size {
+        self.map_source_size(&self.get_orientation(self.scale))
+    }
+
+    /// Where the center of the source goes: the center of the canvas, moved
+    /// by `relative_center_offset` across the space that the canvas and the
+    /// output differ by.
+    fn get_output_center(&self, output_size: &Size) -> Coords {
+        let canvas_size = self.get_canvas_size();
 
         Coords {
-            x: canvas_center.x - center.x
-                + (canvas_center.x - center.x) * self.relative_center_offset.dx,
-            y: canvas_center.y - center.y
-                + (canvas_center.y - center.y) * self.relative_center_offset.dy,
+            x: canvas_size.width / 2.0
+                + (canvas_size.width - output_size.width) / 2.0 * self.relative_center_offset.dx,
+            y: canvas_size.height / 2.0
+                + (canvas_size.height - output_size.height) / 2.0 * self.relative_center_offset.dy,
         }
     }
 
     fn get_output_dimensions(&self) -> Dimensions {
         let output_size = self.get_output_size();
+        let center = self.get_output_center(&output_size);
 
         Dimensions {
-            origin: self.get_output_origin(&output_size),
+            origin: Coords {
+                x: center.x - output_size.width / 2.0,
+                y: center.y - output_size.height / 2.0,
+            },
             size: output_size,
         }
     }
 
     pub fn get_output_pixel_dimensions(&self) -> PixelDimensions {
-        let output_dimensions = self.get_output_dimensions();
+        let output_size = self.get_output_size();
+        let crop = self.get_crop();
+        let (x, y) = crop.affine.apply(0.0, 0.0);
 
         PixelDimensions {
             canvas: PixelSize {
-                width: self.canvas_size.width.round() as u32,
-                height: self.canvas_size.height.round() as u32,
+                width: crop.width.round() as u32,
+                height: crop.height.round() as u32,
             },
             size: PixelSize {
-                width: output_dimensions.size.width.round() as u32,
-                height: output_dimensions.size.height.round() as u32,
+                width: output_size.width.round() as u32,
+                height: output_size.height.round() as u32,
             },
             origin: PixelCoords {
-                x: output_dimensions.origin.x.round() as i32,
-                y: output_dimensions.origin.y.round() as i32,
+                x: x.round() as i32,
+                y: y.round() as i32,
             },
         }
     }
+
+    /// The smallest size that the source can be decoded at while still
+    /// having at least one source pixel for every output pixel.
+    pub fn get_decode_size(&self) -> PixelSize {
+        let input_size = self.get_input_size();
+        let output_size = self.get_output_size();
+
+        let factor = (output_size.width / input_size.width)
+            .max(output_size.height / input_size.height)
+            .min(1.0);
+
+        PixelSize {
+            width: ((self.source_size.width * factor).round() as u32).max(1),
+            height: ((self.source_size.height * factor).round() as u32).max(1),
+        }
+    }
+
+    /// Like `get_affine`, for a source that was decoded at a different size
+    /// than the one the transform was created with.
+    pub fn get_affine_for(&self, source_pixel_size: &PixelSize) -> Affine {
+        Affine::scale(
+            self.source_size.width / source_pixel_size.width as f32,
+            self.source_size.height / source_pixel_size.height as f32,
+        )
+        .then(&self.get_affine())
+    }
+
+    /// The canvas as a window onto the oriented and scaled source, which
+    /// has its bounding box at the origin. The window extends past the
+    /// source where the canvas is padded.
+    fn get_crop(&self) -> Crop {
+        let canvas_size = self.get_canvas_size();
+        let output_dimensions = self.get_output_dimensions();
+
+        Affine::crop(
+            -output_dimensions.origin.x,
+            -output_dimensions.origin.y,
+            canvas_size.width,
+            canvas_size.height,
+        )
+    }
+
+    /// Maps source pixel coordinates onto the canvas, without rounding the
+    /// output size or origin to whole pixels. The output size and origin are
+    /// the bounding box of the source mapped by it.
+    pub fn get_affine(&self) -> Affine {
+        let output_size = self.get_output_size();
+
+        let placement = Affine::translate(
+            -self.source_size.width / 2.0,
+            -self.source_size.height / 2.0,
+        )
+        .then(&self.get_orientation(self.scale))
+        .then(&Affine::translate(
+            output_size.width / 2.0,
+            output_size.height / 2.0,
+        ));
+
+        self.get_crop().after(&placement).affine
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::{
-        Coords, Dimensions, PixelCoords, PixelDimensions, PixelSize, Size, Transform, TransformMode,
+        Coords, Dimensions, Flip, Length, PixelCoords, PixelDimensions, PixelSize, Rotation, Size,
+        Transform, TransformMode,
     };
 
+    /// Compares dimensions up to the rounding of composing the affine.
+    fn assert_near(actual: Dimensions, expected: Dimensions) {
+        let near = |a: f32, b: f32| (a - b).abs() < 1e-4;
+
+        assert!(
+            near(actual.size.width, expected.size.width)
+                && near(actual.size.height, expected.size.height)
+                && near(actual.origin.x, expected.origin.x)
+                && near(actual.origin.y, expected.origin.y),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn resolves_lengths_against_source() {
+        assert_eq!(Length::Pixels(120).to_pixels(600), 120);
+        assert_eq!(Length::Percent(50.0).to_pixels(600), 300);
+        assert_eq!(Length::Percent(33.3).to_pixels(100), 33);
+        assert_eq!(Length::Percent(150.0).to_pixels(3), 5);
+        assert_eq!(Length::Percent(1.0).to_pixels(20), 1);
+    }
+
     #[test]
     fn fixed_ratios() {
         let transform = Transform::new(
@@ -212,15 +394,15 @@ mod test {
 
         let output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords { x: 0.0, y: 0.0 },
                 size: Size {
                     width: 50.0,
-                    height: 50.0
-                }
-            }
+                    height: 50.0,
+                },
+            },
         );
     }
 
@@ -239,15 +421,15 @@ mod test {
 
         let output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords { x: 0.0, y: -2.5 },
                 size: Size {
                     width: 20.0,
-                    height: 30.0
-                }
-            }
+                    height: 30.0,
+                },
+            },
         );
     }
 
@@ -266,18 +448,18 @@ mod test {
 
         let output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords {
                     x: -3.333334,
-                    y: 0.0
+                    y: 0.0,
                 },
                 size: Size {
                     width: 26.666668,
-                    height: 40.0
-                }
-            }
+                    height: 40.0,
+                },
+            },
         );
     }
 
@@ -296,15 +478,15 @@ mod test {
 
         let output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords { x: 0.0, y: -50.0 },
                 size: Size {
                     width: 200.0,
-                    height: 200.0
-                }
-            }
+                    height: 200.0,
+                },
+            },
         );
     }
 
@@ -326,30 +508,30 @@ mod test {
 
         let mut output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords { x: 0.0, y: 0.0 },
                 size: Size {
                     width: 20.0,
-                    height: 30.0
-                }
-            }
+                    height: 30.0,
+                },
+            },
         );
 
         transform.relative_center_offset.dy = 0.0; // Center
         transform.relative_center_offset.dx = 1.0; // Right
 
         output_dimensions = transform.get_output_dimensions();
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords { x: 0.0, y: -2.5 },
                 size: Size {
                     width: 20.0,
-                    height: 30.0
-                }
-            }
+                    height: 30.0,
+                },
+            },
         );
     }
 
@@ -368,18 +550,43 @@ mod test {
 
         let output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords {
                     x: 0.0,
-                    y: 8.333333
+                    y: 8.333333,
                 },
                 size: Size {
                     width: 20.0,
-                    height: 13.333334
-                }
-            }
+                    height: 13.333334,
+                },
+            },
+        );
+    }
+
+    #[test]
+    fn fits_portrait_image_in_taller_canvas() {
+        let transform = Transform::new(
+            &PixelSize {
+                width: 50,
+                height: 100,
+            },
+            TransformMode::Fit {
+                width: 100,
+                height: 300,
+            },
+        );
+
+        assert_near(
+            transform.get_output_dimensions(),
+            Dimensions {
+                origin: Coords { x: 0.0, y: 50.0 },
+                size: Size {
+                    width: 100.0,
+                    height: 200.0,
+                },
+            },
         );
     }
 
@@ -456,15 +663,163 @@ mod test {
 
         let output_dimensions = transform.get_output_dimensions();
 
-        assert_eq!(
+        assert_near(
             output_dimensions,
             Dimensions {
                 origin: Coords { x: -4.0, y: -6.0 },
                 size: Size {
                     width: 48.0,
-                    height: 72.0
-                }
+                    height: 72.0,
+                },
+            },
+        );
+    }
+
+    #[test]
+    fn maps_source_onto_canvas() {
+        let mut transform = Transform::new(
+            &PixelSize {
+                width: 200,
+                height: 300,
+            },
+            TransformMode::Fill {
+                width: 20,
+                height: 25,
+            },
+        );
+
+        let affine = transform.get_affine();
+
+        assert_eq!(affine.apply(0.0, 0.0), (0.0, -2.5));
+        assert_eq!(affine.apply(200.0, 300.0), (20.0, 27.5));
+
+        transform.flip = Flip {
+            horizontal: true,
+            vertical: false,
+        };
+
+        let affine = transform.get_affine();
+
+        assert_eq!(affine.apply(0.0, 0.0), (20.0, -2.5));
+        assert_eq!(affine.apply(200.0, 300.0), (0.0, 27.5));
+    }
+
+    #[test]
+    fn rotates_before_sizing() {
+        let mut transform = Transform::new(
+            &PixelSize {
+                width: 200,
+                height: 100,
+            },
+            TransformMode::FitWidth(50),
+        );
+
+        transform.rotation = Rotation::Clockwise90;
+
+        assert_eq!(
+            transform.get_output_pixel_dimensions(),
+            PixelDimensions {
+                canvas: PixelSize {
+                    width: 50,
+                    height: 100
+                },
+                size: PixelSize {
+                    width: 50,
+                    height: 100
+                },
+                origin: PixelCoords { x: 0, y: 0 }
+            }
+        );
+
+        let affine = transform.get_affine();
+
+        assert_eq!(affine.apply(0.0, 0.0), (50.0, 0.0));
+        assert_eq!(affine.apply(200.0, 100.0), (0.0, 100.0));
+    }
+
+    #[test]
+    fn crops_the_canvas_after_rotating_and_flipping() {
+        let mut transform = Transform::new(
+            &PixelSize {
+                width: 200,
+                height: 100,
+            },
+            TransformMode::Fill {
+                width: 50,
+                height: 50,
+            },
+        );
+
+        transform.rotation = Rotation::Clockwise90;
+        transform.flip = Flip {
+            horizontal: true,
+            vertical: false,
+        };
+
+        assert_eq!(
+            transform.get_output_pixel_dimensions(),
+            PixelDimensions {
+                canvas: PixelSize {
+                    width: 50,
+                    height: 50
+                },
+                size: PixelSize {
+                    width: 50,
+                    height: 100
+                },
+                origin: PixelCoords { x: 0, y: -25 }
+            }
+        );
+
+        let affine = transform.get_affine();
+
+        assert_eq!(affine.apply(0.0, 0.0), (50.0, 75.0));
+        assert_eq!(affine.apply(200.0, 100.0), (0.0, -25.0));
+    }
+
+    #[test]
+    fn decodes_at_output_resolution() {
+        let mut transform = Transform::new(
+            &PixelSize {
+                width: 4000,
+                height: 3000,
+            },
+            TransformMode::Fill {
+                width: 100,
+                height: 100,
+            },
+        );
+
+        assert_eq!(
+            transform.get_decode_size(),
+            PixelSize {
+                width: 133,
+                height: 100
             }
         );
+
+        transform.scale = 100.0;
+
+        assert_eq!(
+            transform.get_decode_size(),
+            PixelSize {
+                width: 4000,
+                height: 3000
+            }
+        );
+
+        let affine = transform.get_affine_for(&PixelSize {
+            width: 500,
+            height: 375,
+        });
+
+        assert_eq!(
+            affine.apply(0.0, 0.0),
+            transform.get_affine().apply(0.0, 0.0)
+        );
+        assert_eq!(
+            affine.apply(500.0, 375.0),
+            transform.get_affine().apply(4000.0, 3000.0)
+        );
     }
 }
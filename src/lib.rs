@@ -1,11 +1,13 @@
 extern crate cfg_if;
 extern crate wasm_bindgen;
 
-mod image;
+mod color;
+#[doc(hidden)]
+pub mod image;
 mod utils;
 
 use cfg_if::cfg_if;
-use serde_wasm_bindgen::from_value;
+use serde_wasm_bindgen::{from_value, to_value};
 use wasm_bindgen::prelude::*;
 
 #[macro_use]
@@ -21,74 +23,515 @@ cfg_if! {
     }
 }
 
-fn positive_int_value(value: u32) -> Option<u32> {
-    if value > 0 {
-        Some(value)
-    } else {
-        None
-    }
-}
-
 #[derive(Serialize, Deserialize)]
 struct ProcessImageParams {
-    bg: Vec<u8>,
+    #[serde(default)]
+    auto: String,
+    #[serde(default)]
+    bg: String,
+    #[serde(default)]
+    brightness: Option<f32>,
+    #[serde(default)]
+    budget: u64,
+    #[serde(default)]
+    contrast: Option<f32>,
     dx: f32,
     dy: f32,
+    #[serde(default)]
+    effect: String,
+    #[serde(default)]
+    exposure: Option<f32>,
+    #[serde(default)]
+    filter: String,
+    #[serde(default)]
+    flip: String,
     format: String,
-    height: u32,
+    #[serde(default)]
+    gamma: Option<f32>,
+    #[serde(deserialize_with = "number_or_string")]
+    height: String,
+    #[serde(default)]
+    highlight: String,
+    #[serde(default)]
+    hue: Option<f32>,
+    #[serde(default)]
+    icc: String,
+    #[serde(default)]
+    limits: LimitParams,
+    #[serde(default)]
+    linear: bool,
     mode: String,
+    #[serde(default)]
+    protect: Vec<f32>,
     quality: u8,
+    #[serde(default)]
+    rotate: u32,
+    #[serde(default)]
+    saturation: Option<f32>,
     scale: f32,
-    width: u32,
+    #[serde(default)]
+    shadow: String,
+    #[serde(deserialize_with = "number_or_string")]
+    width: String,
+}
+
+/// Reads a length as a string, or as a number of pixels like callers passed
+/// before percentages were accepted.
+fn number_or_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct LengthVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for LengthVisitor {
+        type Value = String;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a number or a string")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<String, E> {
+            Ok(value.to_string())
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<String, E> {
+            Ok(value.to_string())
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<String, E> {
+            Ok(value.to_string())
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<String, E> {
+            // Numbers are pixels, which are whole
+            if value.fract() != 0.0 {
+                return Err(E::invalid_value(
+                    serde::de::Unexpected::Float(value),
+                    &"a whole number of pixels",
+                ));
+            }
+
+            Ok(value.to_string())
+        }
+    }
+
+    deserializer.deserialize_any(LengthVisitor)
+}
+
+/// The largest percentage that widths and heights can be given as.
+const MAX_PERCENT: f32 = 10_000.0;
+
+/// Overrides for the default `image::Limits`, set by the worker rather than
+/// the request.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct LimitParams {
+    bytes: Option<u64>,
+    frames: Option<u64>,
+    input_pixels: Option<u64>,
+    output_pixels: Option<u64>,
+    seam_pixels: Option<u64>,
 }
 
 fn error_to_js_value(e: failure::Error) -> JsValue {
     JsValue::from_str(&e.to_string())
 }
 
-
+/// Processes `buffer` with `params_value`, returning the encoded image
+/// followed by one byte for its format: `0` for PNG and `1` for JPEG.
 #[wasm_bindgen]
 pub fn process_image(buffer: &[u8], params_value: JsValue) -> Result<Vec<u8>, JsValue> {
-    console_error_panic_hook::set_once();
+    utils::set_panic_hook();
 
     let params: ProcessImageParams = from_value(params_value)?;
+    let (mut output, output_format) = process_with_params(buffer, &params)?;
 
-    let transform_mode = string_to_transform_mode(
-        &params.mode,
-        positive_int_value(params.width),
-        positive_int_value(params.height),
+    output.push(output_format_to_key(output_format));
+
+    Ok(output)
+}
+
+fn process_with_params(
+    buffer: &[u8],
+    params: &ProcessImageParams,
+) -> Result<(Vec<u8>, image::ImageOutputFormat), JsValue> {
+    let output_format = params_to_output_format(buffer, params).map_err(error_to_js_value)?;
+
+    let limits = params_to_limits(&params.limits);
+    let image_size = image::check_input(buffer, &limits).map_err(error_to_js_value)?;
+
+    let transform = params_to_transform(params, &image_size).map_err(error_to_js_value)?;
+
+    limits
+        .check_output(&transform)
+        .map_err(|e| error_to_js_value(e.into()))?;
+
+    let colors = image::Colors {
+        enhancements: string_to_enhancements(&params.auto).map_err(error_to_js_value)?,
+        adjustments: params_to_adjustments(params).map_err(error_to_js_value)?,
+        background: string_to_background(&params.bg).map_err(error_to_js_value)?,
+        effect: params_to_effect(params).map_err(error_to_js_value)?,
+    };
+
+    let mut sampling = image::Sampling {
+        filter: string_to_filter(&params.filter).map_err(error_to_js_value)?,
+        linear: params.linear,
+    };
+
+    let degradations = params_to_degradations(
+        buffer,
+        params,
+        &image_size,
+        &transform,
+        &mut sampling,
+        &output_format,
     )
-    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    .map_err(error_to_js_value)?;
 
-    let output_format = match string_to_output_format(&params.format, params.quality) {
-        None => {
-            let input_format = image::input_format(buffer).map_err(error_to_js_value)?;
-            image::input_to_output_format(input_format, params.quality)
-                .map_err(error_to_js_value)?
-        }
-        Some(output_format) => output_format,
+    let output = image::process_buffer(
+        buffer,
+        &transform,
+        &sampling,
+        output_format.clone(),
+        &colors,
+        degradations,
+        string_to_keep_profile(&params.icc).map_err(error_to_js_value)?,
+    )
+    .map_err(error_to_js_value)?;
+
+    Ok((output, output_format))
+}
+
+fn params_to_output_format(
+    buffer: &[u8],
+    params: &ProcessImageParams,
+) -> Result<image::ImageOutputFormat, failure::Error> {
+    match string_to_output_format(&params.format, params.quality) {
+        None => image::input_to_output_format(image::input_format(buffer)?, params.quality),
+        Some(output_format) => Ok(output_format),
+    }
+}
+
+fn params_to_degradations(
+    buffer: &[u8],
+    params: &ProcessImageParams,
+    image_size: &image::PixelSize,
+    transform: &image::Transform,
+    sampling: &mut image::Sampling,
+    output_format: &image::ImageOutputFormat,
+) -> Result<image::Degradations, failure::Error> {
+    Ok(image::budget::degrade(
+        params.budget,
+        image_size,
+        image::input_format(buffer)?,
+        transform,
+        sampling,
+        output_format,
+    ))
+}
+
+/// Reads what the headers of `buffer` tell about it, and the dimensions and
+/// degradations that `process_image` would produce with `params_value`, if
+/// given, without decoding any pixels.
+#[wasm_bindgen]
+pub fn probe_image(buffer: &[u8], params_value: JsValue) -> Result<JsValue, JsValue> {
+    utils::set_panic_hook();
+
+    let probe = image::probe(buffer).map_err(error_to_js_value)?;
+
+    let (output, degradations) = if params_value.is_undefined() || params_value.is_null() {
+        (None, Vec::new())
+    } else {
+        let params: ProcessImageParams = from_value(params_value)?;
+        let transform = params_to_transform(&params, &probe.size).map_err(error_to_js_value)?;
+        let output_format = params_to_output_format(buffer, &params).map_err(error_to_js_value)?;
+        let mut sampling = image::Sampling {
+            filter: string_to_filter(&params.filter).map_err(error_to_js_value)?,
+            linear: params.linear,
+        };
+        let degradations = params_to_degradations(
+            buffer,
+            &params,
+            &probe.size,
+            &transform,
+            &mut sampling,
+            &output_format,
+        )
+        .map_err(error_to_js_value)?;
+
+        (
+            Some(OutputDimensions::from(
+                transform.get_output_pixel_dimensions(),
+            )),
+            degradations.names(),
+        )
     };
 
-    let mut image = image::load(buffer).map_err(|e| JsValue::from(e.to_string()))?;
-    let image_size = image::size(&image);
+    Ok(to_value(&ProbeResult {
+        alpha: probe.alpha,
+        degradations,
+        format: input_format_to_string(probe.format).to_string(),
+        frames: probe.frames,
+        height: probe.size.height,
+        output,
+        width: probe.size.width,
+    })?)
+}
+
+/// Computes histograms and other figures of the pixels of `buffer`, decoded
+/// like `process_image` does, or of the output of `process_image` with
+/// `params_value`, if given. The output is decoded again for this, so the
+/// figures are of the encoded pixels, including any JPEG artifacts and the
+/// alpha that JPEGs drop, rather than of the canvas before encoding.
+#[wasm_bindgen]
+pub fn image_stats(buffer: &[u8], params_value: JsValue) -> Result<JsValue, JsValue> {
+    utils::set_panic_hook();
+
+    let image = if params_value.is_undefined() || params_value.is_null() {
+        image::check_input(buffer, &image::Limits::default()).map_err(error_to_js_value)?;
+        image::load_srgb(buffer).map_err(error_to_js_value)?
+    } else {
+        let params: ProcessImageParams = from_value(params_value)?;
+        let (output, _) = process_with_params(buffer, &params)?;
+        image::load(&output).map_err(error_to_js_value)?
+    };
+
+    Ok(to_value(&StatsResult::from(image::stats::compute(&image)))?)
+}
+
+/// The most colors that `extract_palette` finds.
+const MAX_PALETTE_COUNT: u32 = 16;
+
+/// Finds the dominant color and a palette of up to `count` colors of an image,
+/// with the percentage of the visible pixels near each, on a thumbnail.
+#[wasm_bindgen]
+pub fn extract_palette(buffer: &[u8], count: u32) -> Result<JsValue, JsValue> {
+    utils::set_panic_hook();
+
+    if count == 0 || count > MAX_PALETTE_COUNT {
+        return Err(JsValue::from_str(&format!(
+            "count must be between 1 and {}",
+            MAX_PALETTE_COUNT
+        )));
+    }
+
+    image::check_input(buffer, &image::Limits::default()).map_err(error_to_js_value)?;
+    let image = image::load_srgb(buffer).map_err(error_to_js_value)?;
+    let palette = image::palette::extract(&image, count as usize);
+
+    Ok(to_value(&PaletteResult {
+        dominant: palette.first().map(SwatchResult::from),
+        palette: palette.iter().map(SwatchResult::from).collect(),
+    })?)
+}
+
+#[derive(Serialize)]
+struct PaletteResult {
+    dominant: Option<SwatchResult>,
+    palette: Vec<SwatchResult>,
+}
+
+#[derive(Serialize)]
+struct SwatchResult {
+    color: String,
+    population: f32,
+}
+
+impl From<&image::palette::Swatch> for SwatchResult {
+    fn from(swatch: &image::palette::Swatch) -> Self {
+        SwatchResult {
+            color: format!(
+                "#{:02x}{:02x}{:02x}",
+                swatch.color[0], swatch.color[1], swatch.color[2]
+            ),
+            population: swatch.population,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatsResult {
+    alpha: Option<ChannelResult>,
+    blue: ChannelResult,
+    green: ChannelResult,
+    red: ChannelResult,
+    sharpness: f32,
+}
+
+impl From<image::stats::Stats> for StatsResult {
+    fn from(stats: image::stats::Stats) -> Self {
+        StatsResult {
+            alpha: stats.alpha.map(ChannelResult::from),
+            blue: ChannelResult::from(stats.blue),
+            green: ChannelResult::from(stats.green),
+            red: ChannelResult::from(stats.red),
+            sharpness: stats.sharpness,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChannelResult {
+    clipped_highlights: f32,
+    clipped_shadows: f32,
+    histogram: Vec<u32>,
+    mean: f32,
+    std_dev: f32,
+}
+
+impl From<image::stats::Channel> for ChannelResult {
+    fn from(channel: image::stats::Channel) -> Self {
+        ChannelResult {
+            clipped_highlights: channel.clipped_highlights,
+            clipped_shadows: channel.clipped_shadows,
+            histogram: channel.histogram,
+            mean: channel.mean,
+            std_dev: channel.std_dev,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ProbeResult {
+    alpha: bool,
+    degradations: Vec<&'static str>,
+    format: String,
+    frames: u64,
+    height: u32,
+    output: Option<OutputDimensions>,
+    width: u32,
+}
+
+/// The canvas size, and where the image is placed on it.
+#[derive(Serialize)]
+struct OutputDimensions {
+    canvas_height: u32,
+    canvas_width: u32,
+    height: u32,
+    width: u32,
+    x: i32,
+    y: i32,
+}
+
+impl From<image::PixelDimensions> for OutputDimensions {
+    fn from(dimensions: image::PixelDimensions) -> Self {
+        OutputDimensions {
+            canvas_height: dimensions.canvas.height,
+            canvas_width: dimensions.canvas.width,
+            height: dimensions.size.height,
+            width: dimensions.size.width,
+            x: dimensions.origin.x,
+            y: dimensions.origin.y,
+        }
+    }
+}
+
+fn params_to_transform(
+    params: &ProcessImageParams,
+    image_size: &image::PixelSize,
+) -> Result<image::Transform, failure::Error> {
+    let width = string_to_length(&params.width)?;
+    let height = string_to_length(&params.height)?;
+
+    let transform_mode = string_to_transform_mode(
+        &params.mode,
+        width.map(|width| width.to_pixels(image_size.width)),
+        height.map(|height| height.to_pixels(image_size.height)),
+    )?;
 
-    let mut transform = image::Transform::new(&image_size, transform_mode);
+    let mut transform = image::Transform::new(image_size, transform_mode);
     transform.relative_center_offset.dx = params.dx;
     transform.relative_center_offset.dy = params.dy;
     transform.scale = params.scale;
+    transform.rotation = degrees_to_rotation(params.rotate)?;
+    transform.flip = string_to_flip(&params.flip)?;
+    transform.protected_regions = values_to_regions(&params.protect)?;
 
-    let color_option = if params.bg.is_empty() {
-        None
-    } else {
-        Some([params.bg[0], params.bg[1], params.bg[2]])
+    Ok(transform)
+}
+
+fn params_to_limits(params: &LimitParams) -> image::Limits {
+    let defaults = image::Limits::default();
+
+    image::Limits {
+        bytes: params.bytes.unwrap_or(defaults.bytes),
+        frames: params.frames.unwrap_or(defaults.frames),
+        input_pixels: params.input_pixels.unwrap_or(defaults.input_pixels),
+        output_pixels: params.output_pixels.unwrap_or(defaults.output_pixels),
+        seam_pixels: params.seam_pixels.unwrap_or(defaults.seam_pixels),
+    }
+}
+
+/// Fills in the adjustments that were not given with ones that change nothing.
+fn params_to_adjustments(
+    params: &ProcessImageParams,
+) -> Result<image::Adjustments, failure::Error> {
+    let defaults = image::Adjustments::default();
+
+    let adjustments = image::Adjustments {
+        exposure: params.exposure.unwrap_or(defaults.exposure),
+        gamma: params.gamma.unwrap_or(defaults.gamma),
+        brightness: params.brightness.unwrap_or(defaults.brightness),
+        contrast: params.contrast.unwrap_or(defaults.contrast),
+        saturation: params.saturation.unwrap_or(defaults.saturation),
+        hue: params.hue.unwrap_or(defaults.hue),
     };
 
-    let mut output = image::process(&mut image, &transform, output_format.clone(), color_option)
-        .map_err(error_to_js_value)?;
+    for (name, value, minimum) in [
+        ("exposure", adjustments.exposure, f32::MIN),
+        ("brightness", adjustments.brightness, 0.0),
+        ("contrast", adjustments.contrast, 0.0),
+        ("saturation", adjustments.saturation, 0.0),
+        ("hue", adjustments.hue, f32::MIN),
+    ] {
+        if !(value.is_finite() && value >= minimum) {
+            return Err(failure::format_err!("invalid {} {}", name, value));
+        }
+    }
 
-    output.push(output_format_to_key(output_format));
+    if !(adjustments.gamma.is_finite() && adjustments.gamma > 0.0) {
+        return Err(failure::format_err!("gamma must be positive"));
+    }
 
-    Ok(output)
+    Ok(adjustments)
+}
+
+/// Reads enhancements separated by commas, e.g. `levels,clahe`.
+fn string_to_enhancements(auto_string: &str) -> Result<image::Enhancements, failure::Error> {
+    let mut enhancements = image::Enhancements::default();
+
+    for name in auto_string
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match name {
+            "balance" => enhancements.white_balance = true,
+            "levels" => enhancements.levels = true,
+            "clahe" => enhancements.equalize = true,
+            _ => return Err(failure::format_err!("unknown auto {}", name)),
+        }
+    }
+
+    Ok(enhancements)
+}
+
+fn params_to_effect(params: &ProcessImageParams) -> Result<Option<image::Effect>, failure::Error> {
+    match params.effect.as_str() {
+        "" => Ok(None),
+        "grayscale" => Ok(Some(image::Effect::Grayscale)),
+        "sepia" => Ok(Some(image::Effect::Sepia)),
+        "duotone" => {
+            let shadow = string_to_color(&params.shadow)?.unwrap_or([0, 0, 0, 255]);
+            let highlight = string_to_color(&params.highlight)?.unwrap_or([255, 255, 255, 255]);
+
+            Ok(Some(image::Effect::Duotone {
+                shadow: [shadow[0], shadow[1], shadow[2]],
+                highlight: [highlight[0], highlight[1], highlight[2]],
+            }))
+        }
+        _ => Err(failure::format_err!("unknown effect")),
+    }
 }
 
 fn string_to_transform_mode(
@@ -102,10 +545,12 @@ fn string_to_transform_mode(
                 width: width.unwrap(),
                 height: height.unwrap(),
             })
-        } else if width.is_some() {
-            Ok(image::TransformMode::FitWidth(width.unwrap()))
+        } else if let Some(width) = width {
+            Ok(image::TransformMode::FitWidth(width))
+        } else if let Some(height) = height {
+            Ok(image::TransformMode::FitHeight(height))
         } else {
-            Ok(image::TransformMode::FitHeight(height.unwrap()))
+            Err(failure::format_err!("mode needs width or height"))
         }
     } else if width.and(height).is_some() {
         match mode_string {
@@ -117,6 +562,10 @@ fn string_to_transform_mode(
                 width: width.unwrap(),
                 height: height.unwrap(),
             }),
+            "retarget" => Ok(image::TransformMode::Retarget {
+                width: width.unwrap(),
+                height: height.unwrap(),
+            }),
             _ => Err(failure::format_err!("unknown mode")),
         }
     } else {
@@ -124,6 +573,138 @@ fn string_to_transform_mode(
     }
 }
 
+fn string_to_length(length_string: &str) -> Result<Option<image::Length>, failure::Error> {
+    let length = if let Some(percent_string) = length_string.strip_suffix('%') {
+        let percent: f32 = percent_string
+            .parse()
+            .map_err(|_| failure::format_err!("invalid percentage {}", length_string))?;
+        image::Length::Percent(percent)
+    } else if length_string.is_empty() {
+        return Ok(None);
+    } else {
+        let pixels: u32 = length_string
+            .parse()
+            .map_err(|_| failure::format_err!("invalid length {}", length_string))?;
+        image::Length::Pixels(pixels)
+    };
+
+    match length {
+        image::Length::Percent(percent) if percent > 0.0 && percent <= MAX_PERCENT => {
+            Ok(Some(length))
+        }
+        image::Length::Percent(_) => {
+            Err(failure::format_err!("invalid percentage {}", length_string))
+        }
+        image::Length::Pixels(pixels) if pixels > 0 => Ok(Some(length)),
+        image::Length::Pixels(_) => Ok(None),
+    }
+}
+
+fn degrees_to_rotation(degrees: u32) -> Result<image::Rotation, failure::Error> {
+    match degrees % 360 {
+        0 => Ok(image::Rotation::None),
+        90 => Ok(image::Rotation::Clockwise90),
+        180 => Ok(image::Rotation::Clockwise180),
+        270 => Ok(image::Rotation::Clockwise270),
+        _ => Err(failure::format_err!("rotate must be a multiple of 90")),
+    }
+}
+
+fn string_to_flip(flip_string: &str) -> Result<image::Flip, failure::Error> {
+    match flip_string {
+        "" => Ok(image::Flip {
+            horizontal: false,
+            vertical: false,
+        }),
+        "h" => Ok(image::Flip {
+            horizontal: true,
+            vertical: false,
+        }),
+        "v" => Ok(image::Flip {
+            horizontal: false,
+            vertical: true,
+        }),
+        "hv" | "vh" => Ok(image::Flip {
+            horizontal: true,
+            vertical: true,
+        }),
+        _ => Err(failure::format_err!("unknown flip")),
+    }
+}
+
+fn values_to_regions(values: &[f32]) -> Result<Vec<image::Region>, failure::Error> {
+    let regions = values.chunks_exact(4);
+
+    if !regions.remainder().is_empty() {
+        return Err(failure::format_err!(
+            "protect needs groups of x, y, width and height"
+        ));
+    }
+
+    regions
+        .map(|region| {
+            let region = image::Region {
+                x: region[0],
+                y: region[1],
+                width: region[2],
+                height: region[3],
+            };
+
+            if region.x >= 0.0
+                && region.y >= 0.0
+                && region.width > 0.0
+                && region.height > 0.0
+                && region.x + region.width <= 1.0
+                && region.y + region.height <= 1.0
+            {
+                Ok(region)
+            } else {
+                Err(failure::format_err!(
+                    "invalid protect region {},{},{},{}",
+                    region.x,
+                    region.y,
+                    region.width,
+                    region.height
+                ))
+            }
+        })
+        .collect()
+}
+
+fn string_to_filter(filter_string: &str) -> Result<Option<image::Filter>, failure::Error> {
+    match filter_string {
+        "" => Ok(None),
+        "nearest" => Ok(Some(image::Filter::Nearest)),
+        "triangle" => Ok(Some(image::Filter::Triangle)),
+        "catmull-rom" => Ok(Some(image::Filter::CatmullRom)),
+        "gaussian" => Ok(Some(image::Filter::Gaussian)),
+        "lanczos3" => Ok(Some(image::Filter::Lanczos3)),
+        _ => Err(failure::format_err!("unknown filter")),
+    }
+}
+
+fn string_to_background(bg_string: &str) -> Result<Option<image::Background>, failure::Error> {
+    match bg_string {
+        "auto" => Ok(Some(image::Background::Dominant)),
+        _ => Ok(string_to_color(bg_string)?.map(image::Background::Color)),
+    }
+}
+
+fn string_to_color(color_string: &str) -> Result<Option<[u8; 4]>, failure::Error> {
+    match color_string {
+        "" => Ok(None),
+        _ => Ok(Some(color::parse(color_string)?)),
+    }
+}
+
+fn string_to_keep_profile(icc_string: &str) -> Result<bool, failure::Error> {
+    match icc_string {
+        "" | "srgb" => Ok(false),
+        "keep" => Ok(true),
+        _ => Err(failure::format_err!("unknown icc")),
+    }
+}
+
 fn string_to_output_format(format_string: &str, quality: u8) -> Option<image::ImageOutputFormat> {
     match format_string {
         "png" => Some(image::ImageOutputFormat::PNG),
@@ -132,10 +713,112 @@ fn string_to_output_format(format_string: &str, quality: u8) -> Option<image::Im
     }
 }
 
+fn input_format_to_string(input_format: image::ImageFormat) -> &'static str {
+    match input_format {
+        image::ImageFormat::PNG => "png",
+        image::ImageFormat::JPEG => "jpeg",
+        image::ImageFormat::GIF => "gif",
+        image::ImageFormat::WEBP => "webp",
+        _ => "unknown",
+    }
+}
+
 fn output_format_to_key(output_format: image::ImageOutputFormat) -> u8 {
     match output_format {
         image::ImageOutputFormat::PNG => 0,
         image::ImageOutputFormat::JPEG(_) => 1,
         _ => unimplemented!(),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_pixel_and_percentage_lengths() {
+        assert_eq!(string_to_length("").unwrap(), None);
+        assert_eq!(string_to_length("0").unwrap(), None);
+        assert_eq!(
+            string_to_length("180").unwrap(),
+            Some(image::Length::Pixels(180))
+        );
+        assert_eq!(
+            string_to_length("50%").unwrap(),
+            Some(image::Length::Percent(50.0))
+        );
+        assert_eq!(
+            string_to_length("12.5%").unwrap(),
+            Some(image::Length::Percent(12.5))
+        );
+        assert!(string_to_length("half").is_err());
+        assert!(string_to_length("%").is_err());
+        assert!(string_to_length("-5").is_err());
+        assert!(string_to_length("-50%").is_err());
+        assert!(string_to_length("0%").is_err());
+        assert!(string_to_length("NaN%").is_err());
+        assert!(string_to_length("1e30%").is_err());
+        assert_eq!(
+            string_to_length("10000%").unwrap(),
+            Some(image::Length::Percent(10000.0))
+        );
+    }
+
+    #[test]
+    fn reads_lengths_from_numbers_or_strings() {
+        use serde::de::value::{Error, F64Deserializer, StrDeserializer, U64Deserializer};
+
+        assert_eq!(
+            number_or_string(F64Deserializer::<Error>::new(180.0)).unwrap(),
+            "180"
+        );
+        assert_eq!(
+            number_or_string(U64Deserializer::<Error>::new(0)).unwrap(),
+            "0"
+        );
+        assert_eq!(
+            number_or_string(StrDeserializer::<Error>::new("50%")).unwrap(),
+            "50%"
+        );
+        assert!(number_or_string(F64Deserializer::<Error>::new(180.5)).is_err());
+    }
+
+    #[test]
+    fn builds_mode_from_resolved_lengths() {
+        let image_size = image::PixelSize {
+            width: 400,
+            height: 300,
+        };
+        let width = string_to_length("50%").unwrap();
+
+        assert_eq!(
+            string_to_transform_mode(
+                "fit",
+                width.map(|width| width.to_pixels(image_size.width)),
+                None,
+            )
+            .unwrap(),
+            image::TransformMode::FitWidth(200)
+        );
+        assert!(string_to_transform_mode("fit", None, None).is_err());
+    }
+
+    #[test]
+    fn validates_protect_regions() {
+        assert_eq!(
+            values_to_regions(&[0.25, 0.5, 0.5, 0.5]).unwrap(),
+            vec![image::Region {
+                x: 0.25,
+                y: 0.5,
+                width: 0.5,
+                height: 0.5,
+            }]
+        );
+        assert!(values_to_regions(&[0.0, 0.0, 1.0]).is_err());
+        assert!(values_to_regions(&[0.0, 0.0, f32::NAN, 1.0]).is_err());
+        assert!(values_to_regions(&[-0.1, 0.0, 0.5, 0.5]).is_err());
+        assert!(values_to_regions(&[0.75, 0.0, 0.5, 0.5]).is_err());
+        assert!(values_to_regions(&[0.5, 0.5, -0.25, 0.25]).is_err());
+        assert!(values_to_regions(&[0.5, 0.5, 0.0, 0.25]).is_err());
+    }
+}
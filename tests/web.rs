@@ -8,7 +8,7 @@ use wasm_bindgen_test::*;
 wasm_bindgen_test_configure!(run_in_browser);
 
 use base64::decode;
-use image_worker::process_image;
+use image_worker::{extract_palette, image_stats, probe_image, process_image};
 use wasm_bindgen::JsValue;
 
 #[macro_use]
@@ -32,15 +32,15 @@ impl TestImage {
 
 #[derive(Serialize)]
 struct ProcessImageParams {
-    bg: Vec<u8>,
+    bg: String,
     dx: f32,
     dy: f32,
     format: String,
-    height: u32,
+    height: String,
     mode: String,
     quality: u8,
     scale: f32,
-    width: u32,
+    width: String,
 }
 
 #[wasm_bindgen_test]
@@ -54,15 +54,15 @@ fn process_jpeg_image_in_browser() {
     process_image(
         &bytes,
         JsValue::from_serde(&ProcessImageParams {
-            bg: vec![],
+            bg: String::new(),
             dx: 0.0,
             dy: 0.0,
             format: "jpeg".to_string(),
-            height: 100,
+            height: "100".to_string(),
             mode: "fill".to_string(),
             quality: 90,
             scale: 1.0,
-            width: 50,
+            width: "50".to_string(),
         })
         .unwrap(),
     )
@@ -80,17 +80,117 @@ fn process_png_image_in_browser() {
     process_image(
         &bytes,
         JsValue::from_serde(&ProcessImageParams {
-            bg: vec![],
+            bg: String::new(),
+            dx: 0.0,
+            dy: 0.0,
+            format: "png".to_string(),
+            height: "100".to_string(),
+            mode: "fill".to_string(),
+            quality: 90,
+            scale: 1.0,
+            width: "50".to_string(),
+        })
+        .unwrap(),
+    )
+    .unwrap();
+}
+
+#[derive(Deserialize)]
+struct ProbeResult {
+    alpha: bool,
+    format: String,
+    frames: u64,
+    height: u32,
+    width: u32,
+}
+
+#[wasm_bindgen_test]
+fn probe_png_image_in_browser() {
+    let data = TestImage::Png.get_vec();
+
+    let probe: ProbeResult = probe_image(&data, JsValue::UNDEFINED)
+        .unwrap()
+        .into_serde()
+        .unwrap();
+
+    assert_eq!(probe.format, "png");
+    assert_eq!((probe.width, probe.height), (200, 200));
+    assert!(!probe.alpha);
+    assert_eq!(probe.frames, 1);
+}
+
+#[derive(Deserialize)]
+struct PaletteResult {
+    dominant: Option<SwatchResult>,
+    palette: Vec<SwatchResult>,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+struct SwatchResult {
+    color: String,
+    population: f32,
+}
+
+#[wasm_bindgen_test]
+fn extract_png_palette_in_browser() {
+    let data = TestImage::Png.get_vec();
+
+    let result: PaletteResult = extract_palette(&data, 4).unwrap().into_serde().unwrap();
+
+    assert_eq!(result.dominant.as_ref(), result.palette.first());
+    assert!(result.palette.iter().all(|swatch| swatch.color.len() == 7));
+
+    let total: f32 = result.palette.iter().map(|swatch| swatch.population).sum();
+    assert!((total - 100.0).abs() < 0.01);
+    assert!(extract_palette(&data, 0).is_err());
+}
+
+#[derive(Deserialize)]
+struct StatsResult {
+    alpha: Option<ChannelResult>,
+    red: ChannelResult,
+    sharpness: f32,
+}
+
+#[derive(Deserialize)]
+struct ChannelResult {
+    histogram: Vec<u32>,
+    mean: f32,
+}
+
+#[wasm_bindgen_test]
+fn png_image_stats_in_browser() {
+    let data = TestImage::Png.get_vec();
+
+    let source: StatsResult = image_stats(&data, JsValue::UNDEFINED)
+        .unwrap()
+        .into_serde()
+        .unwrap();
+
+    assert_eq!(source.red.histogram.len(), 256);
+    assert_eq!(source.red.histogram.iter().sum::<u32>(), 200 * 200);
+    assert!(source.alpha.is_none());
+    assert!(source.sharpness >= 0.0);
+
+    let output: StatsResult = image_stats(
+        &data,
+        JsValue::from_serde(&ProcessImageParams {
+            bg: String::new(),
             dx: 0.0,
             dy: 0.0,
             format: "png".to_string(),
-            height: 100,
+            height: "100".to_string(),
             mode: "fill".to_string(),
             quality: 90,
             scale: 1.0,
-            width: 50,
+            width: "50".to_string(),
         })
         .unwrap(),
     )
+    .unwrap()
+    .into_serde()
     .unwrap();
-}
\ No newline at end of file
+
+    assert_eq!(output.red.histogram.iter().sum::<u32>(), 50 * 100);
+    assert!((output.red.mean - source.red.mean).abs() < 2.0);
+}
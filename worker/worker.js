@@ -25,7 +25,7 @@ async function handleRequest(req) {
     return res;
   }
 
-  const { process_image } = wasm_bindgen;
+  const { probe_image, process_image } = wasm_bindgen;
 
   let originReq = new Request(params.origin.toString(), req);
   let [originRes] = await Promise.all([
@@ -41,40 +41,95 @@ async function handleRequest(req) {
     }
 
     const data = await originRes.arrayBuffer();
-    const output = process_image(new Uint8Array(data), params);
+    const buffer = new Uint8Array(data);
+    const { degradations } = probe_image(buffer, params);
+    const output = process_image(buffer, params);
     const output_format = output.slice(-1);
 
     res = new Response(output.slice(0, -1), { status: 200 });
     res.headers.set("Content-type", getMimeType(VALID_FORMATS[output_format]));
+    if (degradations.length) {
+      res.headers.set("X-Degradations", degradations.join(", "));
+    }
 
     cache.put(req, res.clone());
     if (originResToCache) {
       cache.put(originReq, originResToCache);
     }
   } catch (e) {
-    res = new Response(e.toString(), { status: 200 });
+    const message = e.toString();
+    let status = 200;
+    if (message.startsWith("too large")) {
+      status = 413;
+    } else if (message.startsWith("invalid color")) {
+      status = 400;
+    }
+    res = new Response(message, { status });
     res.headers.set("Content-type", "text/plain");
   }
   return res;
 }
 
+// Caps on what a request may decode and produce, see `image::Limits`
+const LIMITS = {
+  bytes: 20 * 1024 * 1024,
+  frames: 100,
+  input_pixels: 50000000,
+  output_pixels: 4096 * 4096,
+  seam_pixels: 1000000000
+};
+
+// Estimated cost a request may take before it is degraded, see
+// `image::budget`, or 0 for no limit
+const BUDGET = 2000000000;
+
 const VALID_FORMATS = ["png", "jpg", "jpeg"];
-const VALID_MODES = ["fill", "fit", "limit"];
+const VALID_MODES = ["fill", "fit", "limit", "retarget"];
+const VALID_ROTATIONS = [0, 90, 180, 270];
+const VALID_FLIPS = ["h", "v", "hv"];
+const VALID_ICCS = ["srgb", "keep"];
+const VALID_EFFECTS = ["grayscale", "sepia", "duotone"];
+const VALID_AUTOS = ["balance", "levels", "clahe"];
+const VALID_FILTERS = [
+  "nearest",
+  "triangle",
+  "catmull-rom",
+  "gaussian",
+  "lanczos3"
+];
 
 function getParams(req) {
   const errors = [];
   const params = {
-    bg: [],
+    auto: "",
+    bg: "",
+    brightness: 1,
+    budget: BUDGET,
+    contrast: 1,
     dx: 0,
     dy: 0,
+    effect: "",
     errors,
+    exposure: 0,
+    filter: "",
+    flip: "",
     format: "",
-    height: 0,
+    gamma: 1,
+    height: "",
+    highlight: "",
+    hue: 0,
+    icc: "",
+    limits: LIMITS,
+    linear: false,
     mode: "",
     origin: "",
+    protect: [],
     quality: 90,
+    rotate: 0,
+    saturation: 1,
     scale: 1,
-    width: 0
+    shadow: "",
+    width: ""
   };
 
   const reqUrl = new URL(req.url);
@@ -108,16 +163,16 @@ function getParams(req) {
   }
 
   if (searchParams.has("width")) {
-    params.width = parseInt(searchParams.get("width"), 10);
-    if (!(params.width > -1)) {
-      errors.push("width must be a positive number");
+    params.width = searchParams.get("width");
+    if (!isLength(params.width)) {
+      errors.push("width must be a positive number or percentage");
     }
   }
 
   if (searchParams.has("height")) {
-    params.height = parseInt(searchParams.get("height"), 10);
-    if (!(params.height > -1)) {
-      errors.push("height must be a positive number");
+    params.height = searchParams.get("height");
+    if (!isLength(params.height)) {
+      errors.push("height must be a positive number or percentage");
     }
   }
 
@@ -146,6 +201,108 @@ function getParams(req) {
     }
   }
 
+  if (searchParams.has("rotate")) {
+    params.rotate = parseInt(searchParams.get("rotate"), 10);
+    if (!VALID_ROTATIONS.includes(params.rotate)) {
+      errors.push(`rotate must be one of ${VALID_ROTATIONS.join(", ")}`);
+    }
+  }
+
+  if (searchParams.has("filter")) {
+    params.filter = String(searchParams.get("filter").toLowerCase());
+    if (!VALID_FILTERS.includes(params.filter)) {
+      errors.push(`filter must be one of ${VALID_FILTERS.join(", ")}`);
+    }
+  }
+
+  if (searchParams.has("linear")) {
+    params.linear = ["1", "true"].includes(searchParams.get("linear"));
+  }
+
+  if (searchParams.has("exposure")) {
+    params.exposure = parseFloat(searchParams.get("exposure"));
+    if (!isFinite(params.exposure)) {
+      errors.push("exposure must be a number of stops (default: 0)");
+    }
+  }
+
+  if (searchParams.has("gamma")) {
+    params.gamma = parseFloat(searchParams.get("gamma"));
+    if (!(params.gamma > 0 && isFinite(params.gamma))) {
+      errors.push("gamma must be a positive number (default: 1)");
+    }
+  }
+
+  for (const name of ["brightness", "contrast", "saturation"]) {
+    if (searchParams.has(name)) {
+      params[name] = parseFloat(searchParams.get(name));
+      if (!(params[name] >= 0 && isFinite(params[name]))) {
+        errors.push(`${name} must be a number from 0 (default: 1)`);
+      }
+    }
+  }
+
+  if (searchParams.has("hue")) {
+    params.hue = parseFloat(searchParams.get("hue"));
+    if (!isFinite(params.hue)) {
+      errors.push("hue must be a number of degrees (default: 0)");
+    }
+  }
+
+  if (searchParams.has("auto")) {
+    params.auto = String(searchParams.get("auto").toLowerCase());
+    if (!params.auto.split(",").every(name => VALID_AUTOS.includes(name))) {
+      errors.push(
+        `auto must be one or more of ${VALID_AUTOS.join(", ")}, separated by ,`
+      );
+    }
+  }
+
+  if (searchParams.has("effect")) {
+    params.effect = String(searchParams.get("effect").toLowerCase());
+    if (!VALID_EFFECTS.includes(params.effect)) {
+      errors.push(`effect must be one of ${VALID_EFFECTS.join(", ")}`);
+    }
+  }
+
+  for (const name of ["shadow", "highlight"]) {
+    if (searchParams.has(name)) {
+      params[name] = String(searchParams.get(name));
+    }
+  }
+
+  if (searchParams.has("flip")) {
+    params.flip = String(searchParams.get("flip").toLowerCase());
+    if (!VALID_FLIPS.includes(params.flip)) {
+      errors.push(`flip must be one of ${VALID_FLIPS.join(", ")}`);
+    }
+  }
+
+  if (searchParams.has("icc")) {
+    params.icc = String(searchParams.get("icc").toLowerCase());
+    if (!VALID_ICCS.includes(params.icc)) {
+      errors.push(`icc must be one of ${VALID_ICCS.join(", ")}`);
+    }
+  }
+
+  if (searchParams.has("protect")) {
+    params.protect = String(searchParams.get("protect"))
+      .split(/[;,]/)
+      .map(parseFloat);
+    if (
+      params.protect.length % 4 !== 0 ||
+      params.protect.some(value => !(value >= 0 && value <= 1)) ||
+      params.protect.some(
+        (value, index) =>
+          index % 4 >= 2 && !(value > 0 && params.protect[index - 2] + value <= 1)
+      )
+    ) {
+      errors.push(
+        "protect must be groups of x,y,width,height between 0.0 and 1.0, separated by ;"
+      );
+    }
+  }
+
   if (searchParams.has("mode")) {
     params.mode = String(searchParams.get("mode").toLowerCase());
   }
@@ -155,12 +312,7 @@ function getParams(req) {
   }
 
   if (searchParams.has("bg")) {
-    const bg = getColor(String(searchParams.get("bg")).toLowerCase());
-    if (bg) {
-      params.bg = bg;
-    } else {
-      errors.push("bg must be a valid hex color between 000 and ffffff");
-    }
+    params.bg = String(searchParams.get("bg"));
   }
 
   return params;
@@ -171,24 +323,8 @@ function getUrlExt(url) {
   return extMatch && extMatch[1].toLowerCase();
 }
 
-function getColor(hexStr) {
-  if (hexStr.length === 3) {
-    hexStr = hexStr
-      .split("")
-      .map(c => c + c)
-      .join("");
-  }
-  if (hexStr.length === 6) {
-    const output = [];
-    for (let i = 0; i < 3; i++) {
-      const hex = parseInt(hexStr.slice(0, 2), 16);
-      if (hex === NaN) {
-        return;
-      }
-      output.push(hex);
-    }
-    return output;
-  }
+function isLength(str) {
+  return /^(\d+|\d*\.?\d+%)$/.test(str);
 }
 
 function getMimeType(format) {